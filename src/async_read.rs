@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+
+use crate::stream::record_samples;
+use crate::{MSError, MSFileParam, MSRecord, RecordHeader};
+
+/// Header and decoded numeric samples of a record read asynchronously
+///
+/// Samples are converted to f64 and are empty for text data.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordData {
+    pub header: RecordHeader,
+    pub samples: Vec<f64>,
+}
+
+/// Records read on a blocking thread, see [read_records_async]
+#[derive(Debug)]
+pub struct RecordStream {
+    rx: mpsc::Receiver<Result<RecordData, MSError>>,
+}
+
+impl RecordStream {
+    /// Next record, None at the end of the file or after an error
+    pub async fn recv(&mut self) -> Option<Result<RecordData, MSError>> {
+        self.rx.recv().await
+    }
+}
+
+impl futures_core::Stream for RecordStream {
+    type Item = Result<RecordData, MSError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Read records from a file as a [futures_core::Stream]
+///
+/// The file is read with [MSFileParam] on a thread from tokio's blocking pool,
+/// started with `spawn_blocking`, so this must be called from within a tokio
+/// runtime.  Records are sent over a channel holding at most `capacity`
+/// records; once full the reading thread waits for records to be consumed.
+/// Reading stops after the first error, which is the last item, or when the
+/// stream is dropped.
+pub fn read_records_async<S: AsRef<Path>>(file: S, capacity: usize) -> RecordStream {
+    let (tx, rx) = mpsc::channel(capacity.max(1));
+    let path = file.as_ref().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut fp = MSFileParam::new(path);
+        loop {
+            let item = match fp.read_msr(fp.flags) {
+                Ok(msr) => {
+                    let rec = MSRecord::header_only(msr);
+                    let samples = record_samples(unsafe { &*msr }).unwrap_or_default();
+                    Ok(RecordData {
+                        header: rec.header(),
+                        samples,
+                    })
+                }
+                Err(MSError::EOF) => break,
+                Err(e) => Err(e),
+            };
+            let last = item.is_err();
+            // Receiver dropped, stop reading
+            if tx.blocking_send(item).is_err() || last {
+                break;
+            }
+        }
+    });
+    RecordStream { rx }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    // Run with --features tokio, the runtime is built without tokio's test macros
+    // so no dev-dependency on tokio is needed
+    #[test]
+    fn async_records() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let headers: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+                .map(|r| r.unwrap().header())
+                .collect();
+            let mut stream = read_records_async("./tests/multiple.seed", 8);
+            let mut n = 0;
+            let mut samples = 0;
+            while let Some(r) = stream.recv().await {
+                let r = r.unwrap();
+                assert_eq!(r.header, headers[n]);
+                samples += r.samples.len();
+                n += 1;
+            }
+            assert_eq!(n, headers.len());
+            assert_eq!(samples, 288000);
+
+            let mut stream = read_records_async("./tests/does-not-exist.seed", 8);
+            assert!(matches!(stream.recv().await, Some(Err(_))));
+            assert!(stream.recv().await.is_none());
+        });
+    }
+}
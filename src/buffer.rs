@@ -0,0 +1,459 @@
+use libmseed_sys::MS3Record;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::ptr;
+
+use crate::{check, io_error, read_file, too_short, MSError, MSFileParam, MSRecord};
+
+/// Reads miniSEED records from an in-memory buffer
+pub struct MSBufferParam {
+    buffer: Vec<u8>,
+    // Offset of the next record
+    offset: usize,
+    // Offset of the last record read
+    fpos: usize,
+    // Offset of the buffer within the file it was read from
+    base: usize,
+    verbose: i8,
+    flags: u32,
+}
+
+impl MSBufferParam {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self {
+            buffer,
+            offset: 0,
+            fpos: 0,
+            base: 0,
+            flags: libmseed_sys::MSF_UNPACKDATA,
+            verbose: 0,
+        }
+    }
+    /// Read records from an already open file
+    ///
+    /// The whole file is read into memory, as libmseed requires a path
+    /// to read from a file directly.
+    pub fn from_file(file: File) -> Result<Self, MSError> {
+        read_file(file).map(Self::new)
+    }
+    pub fn unpack_data(&mut self, unpack: bool) {
+        if unpack {
+            self.flags |= libmseed_sys::MSF_UNPACKDATA;
+        } else {
+            self.flags &= !libmseed_sys::MSF_UNPACKDATA;
+        }
+    }
+    pub fn validate_crc(&mut self, validate: bool) {
+        if validate {
+            self.flags |= libmseed_sys::MSF_VALIDATECRC;
+        } else {
+            self.flags &= !libmseed_sys::MSF_VALIDATECRC;
+        }
+    }
+    pub fn verbose(&mut self, verbose: bool) {
+        self.verbose = if verbose { 1 } else { 0 };
+    }
+    /// Offset of the last record read
+    pub fn offset(&self) -> usize {
+        self.base + self.fpos
+    }
+    pub fn read_record(&mut self) -> Result<MSRecord, MSError> {
+        match parse_record(&self.buffer, self.offset, self.flags, self.verbose) {
+            Ok(msr) => {
+                self.fpos = self.offset;
+                self.offset += unsafe { (*msr).reclen } as usize;
+                Ok(MSRecord::owned(msr))
+            }
+            Err(MSError::EOF) => Err(MSError::EOF),
+            Err(e) => {
+                // Unable to resynchronize, stop reading
+                self.offset = self.buffer.len();
+                Err(e)
+            }
+        }
+    }
+}
+
+// Parse the record at `offset` into a new record owned by the caller
+fn parse_record(
+    buffer: &[u8],
+    offset: usize,
+    flags: u32,
+    verbose: i8,
+) -> Result<*mut MS3Record, MSError> {
+    if offset >= buffer.len() || too_short(buffer.len() as u64) {
+        return Err(MSError::EOF);
+    }
+    let rest = &buffer[offset..];
+    let mut msr: *mut MS3Record = ptr::null_mut();
+    let rv = unsafe {
+        libmseed_sys::msr3_parse(
+            rest.as_ptr() as *const _,
+            rest.len() as _,
+            &mut msr,
+            flags,
+            verbose,
+        )
+    };
+    if rv != 0 {
+        unsafe { libmseed_sys::msr3_free(&mut msr) };
+    }
+    if rv > 0 {
+        // Record is incomplete, more data is required
+        return Err(MSError::Error(format!(
+            "Error: truncated record at offset {}",
+            offset
+        )));
+    }
+    check(rv)?;
+    Ok(msr)
+}
+
+// Headers of each record of a borrowed buffer, data samples are not unpacked
+pub(crate) fn scan_records<F: FnMut(&MSRecord)>(buffer: &[u8], mut f: F) -> Result<(), MSError> {
+    let mut offset = 0;
+    loop {
+        let rec = match parse_record(buffer, offset, 0, 0) {
+            Ok(msr) => MSRecord::header_only(msr),
+            Err(MSError::EOF) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        offset += rec.reclen() as usize;
+        f(&rec);
+    }
+}
+
+/// Reads miniSEED records from any [Read] source, e.g. [std::io::stdin]
+///
+/// Only as many bytes as are required for the next record are read, so
+/// records are returned as they arrive from an unbounded stream.
+pub struct MSReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    // Start of unparsed data in the buffer
+    start: usize,
+    // Stream offset of the start of the buffer
+    base: u64,
+    // Stream offset of the last record read
+    fpos: u64,
+    eof: bool,
+    verbose: i8,
+    flags: u32,
+}
+
+impl<R: Read> MSReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            buffer: vec![],
+            start: 0,
+            base: 0,
+            fpos: 0,
+            eof: false,
+            flags: libmseed_sys::MSF_UNPACKDATA,
+            verbose: 0,
+        }
+    }
+    pub fn unpack_data(&mut self, unpack: bool) {
+        if unpack {
+            self.flags |= libmseed_sys::MSF_UNPACKDATA;
+        } else {
+            self.flags &= !libmseed_sys::MSF_UNPACKDATA;
+        }
+    }
+    pub fn validate_crc(&mut self, validate: bool) {
+        if validate {
+            self.flags |= libmseed_sys::MSF_VALIDATECRC;
+        } else {
+            self.flags &= !libmseed_sys::MSF_VALIDATECRC;
+        }
+    }
+    pub fn verbose(&mut self, verbose: bool) {
+        self.verbose = if verbose { 1 } else { 0 };
+    }
+    /// Stream offset of the last record read
+    pub fn offset(&self) -> u64 {
+        self.fpos
+    }
+    // Read up to `n` more bytes, fewer only at the end of the stream
+    fn fill(&mut self, n: usize) -> Result<(), MSError> {
+        let len = self.buffer.len();
+        (&mut self.reader)
+            .take(n as u64)
+            .read_to_end(&mut self.buffer)
+            .map_err(io_error)?;
+        if self.buffer.len() - len < n {
+            self.eof = true;
+        }
+        Ok(())
+    }
+    // Stop reading after an unrecoverable error
+    fn stop(&mut self) {
+        self.buffer.clear();
+        self.start = 0;
+        self.eof = true;
+    }
+    pub fn read_record(&mut self) -> Result<MSRecord, MSError> {
+        // Drop records already returned
+        self.buffer.drain(..self.start);
+        self.base += self.start as u64;
+        self.start = 0;
+        let minreclen = libmseed_sys::MINRECLEN as usize;
+        loop {
+            let len = self.buffer.len();
+            if len < minreclen && !self.eof {
+                self.fill(minreclen - len)?;
+                continue;
+            }
+            if len == 0 {
+                return Err(MSError::EOF);
+            }
+            if too_short(len as u64) {
+                self.stop();
+                return Err(MSError::Error(format!(
+                    "Error: truncated record at offset {}",
+                    self.base
+                )));
+            }
+            // Each record is parsed into a new record owned by the caller
+            let mut msr: *mut MS3Record = ptr::null_mut();
+            let rv = unsafe {
+                libmseed_sys::msr3_parse(
+                    self.buffer.as_ptr() as *const _,
+                    len as _,
+                    &mut msr,
+                    self.flags,
+                    self.verbose,
+                )
+            };
+            if rv != 0 {
+                unsafe { libmseed_sys::msr3_free(&mut msr) };
+            }
+            if rv > 0 {
+                // Record is incomplete, more data is required
+                if self.eof {
+                    self.stop();
+                    return Err(MSError::Error(format!(
+                        "Error: truncated record at offset {}",
+                        self.base
+                    )));
+                }
+                self.fill(rv as usize)?;
+                continue;
+            }
+            if let Err(e) = check(rv) {
+                // Unable to resynchronize, stop reading
+                self.stop();
+                return Err(e);
+            }
+            self.fpos = self.base;
+            self.start = unsafe { (*msr).reclen } as usize;
+            return Ok(MSRecord::owned(msr));
+        }
+    }
+}
+
+impl<R: Read> Iterator for MSReader<R> {
+    type Item = Result<MSRecord, MSError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(x) => Some(Ok(x)),
+            Err(MSError::EOF) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Read the last `n` records of a file without reading the whole file
+///
+/// The file must have a fixed record length, taken from the first record.
+/// An error is returned if the file size is not a multiple of the record
+/// length or the record at the computed offset has a different length.
+/// Offsets of the returned records are relative to the start of the file.
+pub fn read_last_records<S: AsRef<Path>>(file: S, n: usize) -> Result<MSBufferParam, MSError> {
+    let reclen = MSFileParam::new(file.as_ref()).read_header()?.reclen;
+    let mut fp = File::open(file.as_ref()).map_err(io_error)?;
+    let size = fp.metadata().map_err(io_error)?.len();
+    if reclen <= 0 || size % reclen as u64 != 0 {
+        return Err(MSError::Error(format!(
+            "Error: record length is not fixed, {} bytes for record length {}",
+            size, reclen
+        )));
+    }
+    let start = size.saturating_sub(n as u64 * reclen as u64);
+    fp.seek(SeekFrom::Start(start)).map_err(io_error)?;
+    let mut buffer = vec![];
+    fp.read_to_end(&mut buffer).map_err(io_error)?;
+    let mut bp = MSBufferParam::new(buffer);
+    bp.base = start as usize;
+    // Check the first record has the expected length, none if n is 0
+    match bp.read_record() {
+        Ok(rec) if rec.reclen() != reclen => {
+            return Err(MSError::Error(format!(
+                "Error: record length is not fixed, found {} expected {}",
+                rec.reclen(),
+                reclen
+            )));
+        }
+        Ok(_) | Err(MSError::EOF) => {}
+        Err(e) => return Err(e),
+    }
+    bp.offset = 0;
+    bp.fpos = 0;
+    Ok(bp)
+}
+
+// Debug output shows sizes and offsets rather than buffer contents
+impl fmt::Debug for MSBufferParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSBufferParam")
+            .field("buffer_len", &self.buffer.len())
+            .field("offset", &self.offset())
+            .field("flags", &self.flags)
+            .field("verbose", &self.verbose)
+            .finish()
+    }
+}
+
+impl<R: Read> fmt::Debug for MSReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSReader")
+            .field("buffered", &(self.buffer.len() - self.start))
+            .field("offset", &self.fpos)
+            .field("eof", &self.eof)
+            .field("flags", &self.flags)
+            .field("verbose", &self.verbose)
+            .finish()
+    }
+}
+
+impl Iterator for MSBufferParam {
+    type Item = Result<MSRecord, MSError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_record() {
+            Ok(x) => Some(Ok(x)),
+            Err(MSError::EOF) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MSFileParam;
+    #[test]
+    fn buffer_from_file() {
+        let file = File::open("./tests/multiple.seed").unwrap();
+        let bp = MSBufferParam::from_file(file).unwrap();
+        let n0 = MSFileParam::new("./tests/multiple.seed").count();
+        let mut n = 0;
+        for r in bp {
+            assert!(r.is_ok());
+            n += 1;
+        }
+        assert_eq!(n, n0);
+    }
+    #[test]
+    fn buffer_records_owned() {
+        let headers: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let sid = MSBufferParam::new(buf.clone()).read_record().unwrap().sid();
+        assert_eq!(sid, headers[0].sid);
+        // Each record is distinct and outlives the reader
+        let recs: Vec<_> = MSBufferParam::new(buf).map(|r| r.unwrap()).collect();
+        let got: Vec<_> = recs.iter().map(|r| r.header()).collect();
+        assert_eq!(got, headers);
+    }
+    #[test]
+    fn last_records() {
+        let all: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        let mut bp = read_last_records("./tests/multiple.seed", 3).unwrap();
+        let mut last = vec![];
+        let mut offsets = vec![];
+        while let Ok(rec) = bp.read_record() {
+            last.push(rec.header());
+            offsets.push(bp.offset());
+        }
+        assert_eq!(last[..], all[all.len() - 3..]);
+        let size = std::fs::metadata("./tests/multiple.seed").unwrap().len() as usize;
+        assert_eq!(offsets, vec![size - 3 * 512, size - 2 * 512, size - 512]);
+
+        // A corrupt first record is an error rather than fewer records
+        let mut buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let n = buf.len();
+        for b in buf[n - 3 * 512..n - 3 * 512 + 64].iter_mut() {
+            *b = b'x';
+        }
+        let file = std::env::temp_dir().join("libmseed_last_corrupt.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        assert!(read_last_records(&file, 3).is_err());
+        assert!(read_last_records(&file, 2).is_ok());
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn reader_cursor() {
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let headers: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        let mut reader = MSReader::new(std::io::Cursor::new(buf));
+        let mut n = 0;
+        while let Ok(rec) = reader.read_record() {
+            assert_eq!(rec.header(), headers[n]);
+            assert_eq!(reader.offset(), n as u64 * 512);
+            n += 1;
+        }
+        assert_eq!(n, headers.len());
+
+        // Records remain valid after reading further records
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let recs: Vec<_> = MSReader::new(std::io::Cursor::new(buf))
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(recs.len(), headers.len());
+        assert_eq!(recs[0].header(), headers[0]);
+        assert_eq!(recs[recs.len() - 1].header(), headers[headers.len() - 1]);
+
+        let mut buf = std::fs::read("./tests/multiple.seed").unwrap();
+        buf.truncate(700);
+        let mut reader = MSReader::new(std::io::Cursor::new(buf));
+        assert!(matches!(reader.next(), Some(Ok(_))));
+        assert!(matches!(reader.next(), Some(Err(MSError::Error(_)))));
+        assert!(reader.next().is_none());
+    }
+    #[test]
+    fn buffer_debug() {
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let mut bp = MSBufferParam::new(buf.clone());
+        bp.read_record().unwrap();
+        let s = format!("{:?}", bp);
+        assert!(s.contains(&format!("buffer_len: {}", buf.len())));
+        assert!(s.contains("offset: 0"));
+        let mut reader = MSReader::new(std::io::Cursor::new(buf));
+        reader.read_record().unwrap();
+        reader.read_record().unwrap();
+        let s = format!("{:?}", reader);
+        assert!(s.starts_with("MSReader { buffered: 0, offset: 512"));
+    }
+    #[test]
+    fn buffer_corrupt() {
+        let mut bp = MSBufferParam::new(vec![b'x'; 1000]);
+        assert!(matches!(bp.next(), Some(Err(MSError::Error(_)))));
+        assert!(bp.next().is_none());
+
+        // Record truncated part way through
+        let mut buf = std::fs::read("./tests/multiple.seed").unwrap();
+        buf.truncate(700);
+        let mut bp = MSBufferParam::new(buf);
+        assert!(matches!(bp.next(), Some(Ok(_))));
+        assert!(matches!(bp.next(), Some(Err(MSError::Error(_)))));
+        assert!(bp.next().is_none());
+    }
+}
@@ -1,51 +1,104 @@
 use libmseed_sys::MS3FileParam;
 use libmseed_sys::MS3Record;
+use libmseed_sys::MS3RecordPtr;
 use libmseed_sys::MS3TraceID;
 use libmseed_sys::MS3TraceList;
 use libmseed_sys::MS3TraceSeg;
-use std::ffi::CString;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::ffi::{c_void, CString};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::marker::PhantomData;
 use std::path::Path;
 use std::ptr;
 
 use std::slice::from_raw_parts;
 
+#[cfg(feature = "tokio")]
+mod async_read;
+mod buffer;
+mod pack;
+mod select;
+mod stream;
+mod validate;
+mod writer;
+#[cfg(feature = "tokio")]
+pub use async_read::{read_records_async, RecordData, RecordStream};
+pub use buffer::{read_last_records, MSBufferParam, MSReader};
+pub use pack::{ByteOrder, MSPackParam};
+pub use select::Selections;
+pub use stream::{SkipErrors, StreamSegment, StreamSegments};
+pub use validate::{
+    out_of_order_records, validate_file, RecordIssue, TimeOrderCheck, ValidationIssue,
+    ValidationReport,
+};
+pub use writer::{FlushPolicy, MSWriter};
+
 const MS_NOERROR: i32 = libmseed_sys::MS_NOERROR as i32;
 const MS_ENDOFFILE: i32 = libmseed_sys::MS_ENDOFFILE as i32;
 
-#[derive(Debug)]
+// Transparent so a record pointer held in a trace list can be borrowed as a record
+#[repr(transparent)]
 pub struct MSRecord(*mut MS3Record);
 
-#[derive(Debug)]
 pub struct MSFileParam {
     path: String,
-    mspath: CString,
+    // None if the path contains a null byte
+    mspath: Option<CString>,
     msfp: *mut MS3FileParam,
     fpos: i64,
     last: i8,
     verbose: i8,
     flags: u32,
+    // Fixed record length reading, bypasses libmseed's file reader
+    reclen: Option<usize>,
+    file: Option<File>,
+    recbuf: Vec<u8>,
 }
 
-#[derive(Debug)]
 pub struct MSTraceList {
     // Pointer to Miniseed Trace List
     mstl: *mut MS3TraceList,
     // Miniseed file name
     path: String,
+    // In memory data, read in place of the file
+    buffer: Option<Vec<u8>>,
+    splitversion: i8,
+    verbose: i8,
+    flags: u32,
+    selections: Option<Selections>,
+    // Count records while reading, see count_records
+    count: bool,
+    // Records read and not added by the last read, if counted
+    nread: Option<u64>,
+    nskipped: Option<u64>,
 }
 
-#[derive(Debug)]
-pub struct MSTraceID(*mut MS3TraceID);
-#[derive(Debug)]
-pub struct MSTraceSegment(*mut MS3TraceSeg);
+// Traces, segments and record pointers borrow the trace list they point into,
+// segments also point to the trace they belong to
+pub struct MSTraceID<'a>(*mut MS3TraceID, PhantomData<&'a MSTraceList>);
+pub struct MSTraceSegment<'a>(
+    *mut MS3TraceSeg,
+    *mut MS3TraceID,
+    PhantomData<&'a MSTraceList>,
+);
 
-#[derive(Debug)]
-pub struct MSTraceIDIterator {
+pub struct MSTraceIDIterator<'a> {
     mstid: *mut MS3TraceID,
+    _mstl: PhantomData<&'a MSTraceList>,
 }
-#[derive(Debug)]
-pub struct MSTraceSegmentIterator {
+pub struct MSTraceSegmentIterator<'a> {
     mstseg: *mut MS3TraceSeg,
+    mstid: *mut MS3TraceID,
+    _mstl: PhantomData<&'a MSTraceList>,
+}
+
+pub struct MSRecordPtr<'a>(*mut MS3RecordPtr, PhantomData<&'a MSTraceList>);
+pub struct MSRecordPtrIterator<'a> {
+    recptr: *mut MS3RecordPtr,
+    _mstl: PhantomData<&'a MSTraceList>,
 }
 
 #[derive(Debug, Eq, PartialEq, Copy, Clone)]
@@ -53,6 +106,79 @@ pub enum MSSampleType {
     Integer32,
     Float32,
     Float64,
+    Text,
+}
+
+/// Owned copy of the header fields of a record
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordHeader {
+    pub sid: String,
+    pub start_nstime: i64,
+    pub samprate: f64,
+    pub samplecnt: i64,
+    pub reclen: i32,
+    pub encoding: i16,
+    pub pubversion: u8,
+    pub flags: u8,
+}
+
+/// Network, station, location and channel codes of a source id
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceId {
+    pub network: String,
+    pub station: String,
+    pub location: String,
+    pub channel: String,
+}
+
+/// Difference in the data of two trace lists, see [MSTraceList::data_diff]
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataDiff {
+    /// Source id only present in the first list
+    OnlyLeft(String),
+    /// Source id only present in the second list
+    OnlyRight(String),
+    /// Source id of more than one trace in either list, e.g. publication
+    /// versions read with [MSTraceList::split_version], traces are not compared
+    Duplicate(String),
+    StartTime {
+        sid: String,
+        left: time::OffsetDateTime,
+        right: time::OffsetDateTime,
+    },
+    /// Number of samples, including gaps
+    Length {
+        sid: String,
+        left: usize,
+        right: usize,
+    },
+    /// Samples differ, index of the first difference and number of differences
+    Samples {
+        sid: String,
+        first: usize,
+        count: usize,
+    },
+}
+
+/// Time span covered by two segments of the same trace, see [MSTraceList::overlaps]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap {
+    pub sid: String,
+    pub start: time::OffsetDateTime,
+    pub end: time::OffsetDateTime,
+    /// Index of the earlier segment within the trace
+    pub first: usize,
+    /// Index of the later segment within the trace
+    pub second: usize,
+}
+
+/// Timestamp format of CSV output
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum CsvTime {
+    /// e.g. 2020-01-01T00:00:00.000000000
+    Iso,
+    /// Seconds since the UNIX epoch, e.g. 1577836800.000000000
+    Epoch,
 }
 
 #[derive(Debug)]
@@ -62,51 +188,425 @@ pub enum MSError {
 }
 
 impl MSTraceList {
+    /// Read a trace list from a file with the default options
+    ///
+    /// Use [MSTraceList::new] and [MSTraceList::read] to change options
+    /// before reading.
+    pub fn open<S: AsRef<Path>>(file: S) -> Result<Self, MSError> {
+        let mut mstl = Self::new(file);
+        mstl.read()?;
+        Ok(mstl)
+    }
     pub fn new<S: AsRef<Path>>(file: S) -> Self {
         let path: String = file.as_ref().to_string_lossy().into_owned();
         let mstl: *mut MS3TraceList = ptr::null_mut();
-        MSTraceList { mstl, path }
+        MSTraceList {
+            mstl,
+            path,
+            buffer: None,
+            splitversion: 0,
+            verbose: 0,
+            flags: libmseed_sys::MSF_UNPACKDATA,
+            selections: None,
+            count: false,
+            nread: None,
+            nskipped: None,
+        }
+    }
+    /// Trace list read from an in-memory buffer of miniSEED records
+    pub fn from_buffer(buffer: Vec<u8>) -> Self {
+        let mut mstl = Self::new("");
+        mstl.buffer = Some(buffer);
+        mstl
+    }
+    /// Trace list read from an already open file
+    ///
+    /// The whole file is read into memory, as libmseed requires a path
+    /// to read from a file directly.
+    pub fn from_file(file: File) -> Result<Self, MSError> {
+        Ok(Self::from_buffer(read_file(file)?))
+    }
+    pub fn unpack_data(&mut self, unpack: bool) {
+        if unpack {
+            self.flags |= libmseed_sys::MSF_UNPACKDATA;
+        } else {
+            self.flags &= !libmseed_sys::MSF_UNPACKDATA;
+        }
+    }
+    pub fn validate_crc(&mut self, validate: bool) {
+        if validate {
+            self.flags |= libmseed_sys::MSF_VALIDATECRC;
+        } else {
+            self.flags &= !libmseed_sys::MSF_VALIDATECRC;
+        }
+    }
+    pub fn verbose(&mut self, verbose: bool) {
+        self.verbose = if verbose { 1 } else { 0 };
     }
-    pub fn read(&mut self) {
-        let mspath = CString::new(self.path.clone()).unwrap();
-        let verbose = 0;
-        let splitversion = 0;
-        let flags = libmseed_sys::MSF_UNPACKDATA;
+    pub fn split_version(&mut self, split: bool) {
+        self.splitversion = if split { 1 } else { 0 };
+    }
+    /// Retain the records contributing to each segment, see [MSTraceSegment::records]
+    pub fn record_list(&mut self, keep: bool) {
+        if keep {
+            self.flags |= libmseed_sys::MSF_RECORDLIST;
+        } else {
+            self.flags &= !libmseed_sys::MSF_RECORDLIST;
+        }
+    }
+    /// Only read records matching the selections
+    ///
+    /// Reading with empty selections, see [Selections::new], is an error.
+    pub fn selections(&mut self, selections: Option<Selections>) {
+        self.selections = selections;
+    }
+    /// Count the records read and skipped, see [MSTraceList::records_read]
+    ///
+    /// libmseed only reports the number of records added from a buffer, so
+    /// counting reads files, and buffers read with selections, twice: once
+    /// by libmseed into the trace list, then again parsing only the record
+    /// headers to count them.  Counting a large file doubles the I/O.
+    pub fn count_records(&mut self, count: bool) {
+        self.count = count;
+    }
+    /// Read the file or buffer into the trace list
+    ///
+    /// Reading again appends the records to the existing trace list, so the
+    /// same data is added twice.
+    pub fn read(&mut self) -> Result<(), MSError> {
+        let verbose = self.verbose;
+        let splitversion = self.splitversion;
+        let flags = self.flags;
         let tolerance = ptr::null_mut();
+        // libmseed reads every record given no selections
+        if self.selections.as_ref().map_or(false, Selections::is_empty) {
+            return Err(MSError::Error(
+                "Error: empty selections match no records".into(),
+            ));
+        }
+        let selections = self
+            .selections
+            .as_ref()
+            .map(|s| s.as_ptr())
+            .unwrap_or(ptr::null_mut());
+        self.nread = None;
+        self.nskipped = None;
+        let empty = match &self.buffer {
+            Some(buffer) => too_short(buffer.len() as u64),
+            None => file_too_short(&self.path),
+        };
+        if empty {
+            // No records, an empty trace list
+            if self.mstl.is_null() {
+                self.mstl = unsafe { libmseed_sys::mstl3_init(ptr::null_mut()) };
+            }
+            if self.count {
+                self.nread = Some(0);
+                self.nskipped = Some(0);
+            }
+            return Ok(());
+        }
+        if let Some(buffer) = &self.buffer {
+            let rv = unsafe {
+                libmseed_sys::mstl3_readbuffer_selection(
+                    (&mut self.mstl) as *mut *mut MS3TraceList,
+                    buffer.as_ptr() as *const _,
+                    buffer.len() as _,
+                    splitversion,
+                    flags,
+                    tolerance,
+                    selections,
+                    verbose,
+                )
+            };
+            let added = check_count(rv)? as u64;
+            self.detach_records();
+            if !self.count {
+                return Ok(());
+            }
+            match &self.selections {
+                // Count all records, libmseed counts only selected records
+                Some(sel) => {
+                    let (mut nread, mut nskipped) = (0, 0);
+                    crate::buffer::scan_records(buffer, |rec| {
+                        nread += 1;
+                        if !sel.matches(rec) {
+                            nskipped += 1;
+                        }
+                    })?;
+                    self.nread = Some(nread);
+                    self.nskipped = Some(nskipped);
+                }
+                None => {
+                    self.nread = Some(added);
+                    self.nskipped = Some(0);
+                }
+            }
+            return Ok(());
+        }
+        let mspath = path_to_cstring(&self.path)?;
         let rv = unsafe {
-            libmseed_sys::ms3_readtracelist(
+            libmseed_sys::ms3_readtracelist_selection(
                 (&mut self.mstl) as *mut *mut MS3TraceList,
                 mspath.as_ptr(),
                 tolerance,
+                selections,
                 splitversion,
                 flags,
                 verbose,
             )
         };
-        assert_eq!(rv, MS_NOERROR);
+        check(rv)?;
+        self.detach_records();
+        if !self.count {
+            return Ok(());
+        }
+        // libmseed does not report the number of records read from a file
+        let (mut nread, mut nskipped) = (0, 0);
+        let mut fp = MSFileParam::new(&self.path);
+        fp.unpack_data(false);
+        loop {
+            let rec = match fp.read_lazy() {
+                Ok(rec) => rec,
+                Err(MSError::EOF) => break,
+                Err(e) => return Err(e),
+            };
+            nread += 1;
+            if let Some(sel) = &self.selections {
+                if !sel.matches(&rec.rec) {
+                    nskipped += 1;
+                }
+            }
+        }
+        self.nread = Some(nread);
+        self.nskipped = Some(nskipped);
+        Ok(())
+    }
+    // Records of the record list point into the buffer they were parsed from,
+    // which libmseed frees once a file is read
+    fn detach_records(&self) {
+        for (_, seg) in self.all_segments() {
+            for rec in seg.records() {
+                let msr = rec.ptr().msr;
+                if !msr.is_null() {
+                    unsafe { (*msr).record = ptr::null() };
+                }
+            }
+        }
     }
-    fn ptr(&self) -> MS3TraceList {
-        unsafe { *self.mstl }
+    /// Number of records read by [MSTraceList::read]
+    ///
+    /// None unless records are counted, see [MSTraceList::count_records].
+    pub fn records_read(&self) -> Option<u64> {
+        self.nread
+    }
+    /// Number of records read but not added to the trace list by [MSTraceList::read],
+    /// i.e. not matching the selections
+    ///
+    /// None unless records are counted, see [MSTraceList::count_records].
+    pub fn records_skipped(&self) -> Option<u64> {
+        self.nskipped
+    }
+    // None if the trace list has not been read
+    fn ptr(&self) -> Option<MS3TraceList> {
+        if self.mstl.is_null() {
+            None
+        } else {
+            Some(unsafe { *self.mstl })
+        }
     }
+    /// Number of traces, 0 if the trace list has not been read
     pub fn numtraces(&self) -> u32 {
-        self.ptr().numtraces
+        self.ptr().map(|x| x.numtraces).unwrap_or(0)
     }
-    pub fn traces(&self) -> MSTraceIDIterator {
+    /// Traces in the list, empty if the trace list has not been read
+    pub fn traces(&self) -> MSTraceIDIterator<'_> {
         MSTraceIDIterator {
-            mstid: self.ptr().traces,
+            mstid: self.ptr().map(|x| x.traces).unwrap_or(ptr::null_mut()),
+            _mstl: PhantomData,
+        }
+    }
+    /// Every segment of every trace, paired with the trace it belongs to
+    pub fn all_segments(&self) -> impl Iterator<Item = (MSTraceID<'_>, MSTraceSegment<'_>)> {
+        self.traces().flat_map(|id| {
+            let p = id.0;
+            id.segments()
+                .map(move |seg| (MSTraceID(p, PhantomData), seg))
+        })
+    }
+    /// Number of records of each encoding
+    ///
+    /// Requires the record list to be retained, see [MSTraceList::record_list],
+    /// an error is returned otherwise.
+    pub fn encodings(&self) -> Result<HashMap<MSEncoding, u64>, MSError> {
+        let mut out = HashMap::new();
+        for (_, seg) in self.all_segments() {
+            if seg.ptr().recordlist.is_null() {
+                return Err(MSError::Error("Error: record list not retained".into()));
+            }
+            for rec in seg.records() {
+                *out.entry(rec.record().encoding()).or_insert(0) += 1;
+            }
+        }
+        Ok(out)
+    }
+    /// Distinct channels in the trace list, sorted
+    ///
+    /// Data samples are not needed, disable [MSTraceList::unpack_data]
+    /// before reading for a quick scan of a file.
+    pub fn channels(&self) -> Vec<SourceId> {
+        let mut out: Vec<SourceId> = self.traces().map(|t| t.source_id()).collect();
+        out.sort();
+        out.dedup();
+        out
+    }
+    /// Compare decoded samples of traces with matching source ids
+    ///
+    /// Record length, encoding and publication version are ignored.
+    /// Samples are compared as concatenated with [MSTraceID::to_vec_opt_f64],
+    /// so gaps must also match.  Source ids of several traces are reported
+    /// as [DataDiff::Duplicate] rather than compared.
+    pub fn data_diff(&self, other: &MSTraceList) -> Vec<DataDiff> {
+        fn by_sid(mstl: &MSTraceList) -> BTreeMap<String, Vec<MSTraceID>> {
+            let mut out: BTreeMap<String, Vec<MSTraceID>> = BTreeMap::new();
+            for t in mstl.traces() {
+                out.entry(t.sid()).or_default().push(t);
+            }
+            out
+        }
+        let (left, right) = (by_sid(self), by_sid(other));
+        let mut out = vec![];
+        for (sid, a) in &left {
+            let (a, b) = match (a.as_slice(), right.get(sid).map(Vec::as_slice)) {
+                ([a], Some([b])) => (a, b),
+                ([_], None) => {
+                    out.push(DataDiff::OnlyLeft(sid.clone()));
+                    continue;
+                }
+                _ => {
+                    out.push(DataDiff::Duplicate(sid.clone()));
+                    continue;
+                }
+            };
+            if a.start_time() != b.start_time() {
+                out.push(DataDiff::StartTime {
+                    sid: sid.clone(),
+                    left: a.start_time(),
+                    right: b.start_time(),
+                });
+            }
+            let (va, vb) = (a.to_vec_opt_f64(), b.to_vec_opt_f64());
+            if va.len() != vb.len() {
+                out.push(DataDiff::Length {
+                    sid: sid.clone(),
+                    left: va.len(),
+                    right: vb.len(),
+                });
+            }
+            let mut diffs = va
+                .iter()
+                .zip(vb.iter())
+                .enumerate()
+                .filter(|(_, (x, y))| x != y);
+            if let Some((first, _)) = diffs.next() {
+                out.push(DataDiff::Samples {
+                    sid: sid.clone(),
+                    first,
+                    count: 1 + diffs.count(),
+                });
+            }
+        }
+        for (sid, b) in right.iter().filter(|(sid, _)| !left.contains_key(*sid)) {
+            if b.len() > 1 {
+                out.push(DataDiff::Duplicate(sid.clone()));
+            } else {
+                out.push(DataDiff::OnlyRight(sid.clone()));
+            }
+        }
+        out
+    }
+    /// Overlapping segments of each trace
+    ///
+    /// Segments overlap if the first sample of one is within half a sample
+    /// period after the last sample of an earlier segment, or before it.
+    /// The overlap runs from the start of the later segment to the earlier
+    /// of the segment end times.
+    pub fn overlaps(&self) -> Vec<Overlap> {
+        let mut out = vec![];
+        for trace in self.traces() {
+            let segs: Vec<MS3TraceSeg> = trace.segments().map(|s| s.ptr()).collect();
+            for (i, a) in segs.iter().enumerate() {
+                for (j, b) in segs.iter().enumerate().skip(i + 1) {
+                    let ((first, a), (second, b)) = if a.starttime <= b.starttime {
+                        ((i, a), (j, b))
+                    } else {
+                        ((j, b), (i, a))
+                    };
+                    let rate = samprate_hz(a.samprate);
+                    let half = if rate > 0.0 {
+                        (0.5 / rate * libmseed_sys::NSTMODULUS as f64) as i64
+                    } else {
+                        0
+                    };
+                    if b.starttime < a.endtime + half {
+                        out.push(Overlap {
+                            sid: trace.sid(),
+                            start: nstime_to_time(b.starttime),
+                            end: nstime_to_time(a.endtime.min(b.endtime)),
+                            first,
+                            second,
+                        });
+                    }
+                }
+            }
+        }
+        out
+    }
+    /// True if the decoded data of both lists match, see [MSTraceList::data_diff]
+    pub fn data_eq(&self, other: &MSTraceList) -> bool {
+        self.data_diff(other).is_empty()
+    }
+    /// Convert the stored samples of every segment to sample type `t` in place
+    ///
+    /// Later reads of samples of type `t`, e.g. [MSTraceSegment::samples_f64],
+    /// borrow the converted samples instead of converting on every read.
+    /// Text segments and segments without unpacked data are left unchanged.
+    /// Converting float samples to Integer32 fails if any sample is not a
+    /// whole number.
+    pub fn convert(&mut self, t: MSSampleType) -> Result<(), MSError> {
+        for (_, seg) in self.all_segments() {
+            if seg.data_unpacked() && seg.sampletype()? != MSSampleType::Text {
+                seg.convert(t)?;
+            }
+        }
+        Ok(())
+    }
+    /// Merge adjacent segments of every trace whose time gap is within `tolerance` seconds
+    ///
+    /// Segments must have matching sample rates and either both be unpacked
+    /// with the same sample type or both have no data unpacked.  Merged
+    /// segments are freed, so no traces or segments may be held while merging.
+    pub fn merge(&mut self, tolerance: f64) {
+        for trace in self.traces() {
+            trace.merge_within(tolerance);
         }
     }
 }
 
-impl MSTraceID {
+impl<'a> MSTraceID<'a> {
     fn ptr(&self) -> MS3TraceID {
         unsafe { *self.0 }
     }
-    pub fn segments(&self) -> MSTraceSegmentIterator {
+    pub fn segments(&self) -> MSTraceSegmentIterator<'a> {
         MSTraceSegmentIterator {
             mstseg: self.ptr().first,
+            mstid: self.0,
+            _mstl: PhantomData,
         }
     }
+    pub fn sid(&self) -> String {
+        i8_to_string(&(self.ptr().sid))
+    }
     pub fn network(&self) -> String {
         sid_to_nslc(&self.ptr().sid).net
     }
@@ -119,6 +619,9 @@ impl MSTraceID {
     pub fn channel(&self) -> String {
         sid_to_nslc(&self.ptr().sid).cha
     }
+    pub fn source_id(&self) -> SourceId {
+        sid_to_nslc(&self.ptr().sid).into()
+    }
     pub fn start_time(&self) -> time::OffsetDateTime {
         nstime_to_time(self.ptr().earliest)
     }
@@ -131,32 +634,371 @@ impl MSTraceID {
     pub fn numsegments(&self) -> u32 {
         self.ptr().numsegments
     }
+    /// Write `time,value` rows for every sample of every segment to `path`
+    pub fn write_csv<S: AsRef<Path>>(&self, path: S, format: CsvTime) -> Result<(), MSError> {
+        let file = File::create(path).map_err(io_error)?;
+        let mut w = std::io::BufWriter::new(file);
+        self.write_csv_to(&mut w, format)?;
+        w.flush().map_err(io_error)
+    }
+    /// Write `time,value` rows for every sample, text segments are skipped
+    pub fn write_csv_to<W: Write>(&self, w: &mut W, format: CsvTime) -> Result<(), MSError> {
+        writeln!(w, "time,value").map_err(io_error)?;
+        // Rows are written straight from the unpacked samples without a copy
+        fn rows<T: Copy + Into<f64>, W: Write>(
+            w: &mut W,
+            v: &[T],
+            s: &MS3TraceSeg,
+            format: CsvTime,
+        ) -> std::io::Result<()> {
+            for (i, v) in v.iter().enumerate() {
+                let v: f64 = (*v).into();
+                let t = unsafe {
+                    libmseed_sys::ms_sampletime(s.starttime, i as i64, samprate_hz(s.samprate))
+                };
+                match format {
+                    CsvTime::Iso => writeln!(
+                        w,
+                        "{},{}",
+                        nstime_format(
+                            t,
+                            libmseed_sys::ms_timeformat_t_ISOMONTHDAY,
+                            libmseed_sys::ms_subseconds_t_NANO
+                        ),
+                        v
+                    ),
+                    CsvTime::Epoch => {
+                        let modulus = libmseed_sys::NSTMODULUS as i64;
+                        let (sec, ns) = (t.div_euclid(modulus), t.rem_euclid(modulus));
+                        writeln!(w, "{}.{:09},{}", sec, ns, v)
+                    }
+                }?;
+            }
+            Ok(())
+        }
+        for seg in self.segments() {
+            if !seg.data_unpacked() {
+                continue;
+            }
+            let s = seg.ptr();
+            match seg.sampletype() {
+                Ok(MSSampleType::Integer32) => rows(w, unsafe { seg.raw::<i32>() }, &s, format),
+                Ok(MSSampleType::Float32) => rows(w, unsafe { seg.raw::<f32>() }, &s, format),
+                Ok(MSSampleType::Float64) => rows(w, unsafe { seg.raw::<f64>() }, &s, format),
+                Ok(MSSampleType::Text) | Err(_) => Ok(()),
+            }
+            .map_err(io_error)?;
+        }
+        Ok(())
+    }
+    /// Samples of all segments concatenated with gaps between segments as `None`
+    ///
+    /// The number of missing samples in a gap is taken from the sample rate
+    /// of the earlier segment, overlapping segments are appended as is.
+    pub fn to_vec_opt_f64(&self) -> Vec<Option<f64>> {
+        let mut out = vec![];
+        let mut prev: Option<MS3TraceSeg> = None;
+        for seg in self.segments() {
+            let s = seg.ptr();
+            if let Some(p) = prev {
+                out.extend(std::iter::repeat(None).take(missing_samples(&p, &s)));
+            }
+            out.extend(seg.samples_f64().iter().map(|v| Some(*v)));
+            prev = Some(s);
+        }
+        out
+    }
+    /// Segments paired with the offset of their first sample if all
+    /// segments were concatenated into a single array
+    pub fn segments_with_offsets(&self) -> Vec<(usize, MSTraceSegment<'a>)> {
+        let mut offset = 0;
+        self.segments()
+            .map(|seg| {
+                let i = offset;
+                offset += seg.samplecnt().max(0) as usize;
+                (i, seg)
+            })
+            .collect()
+    }
+    /// Samples of each segment as f64, one array per segment
+    ///
+    /// Arrays are in the same order as [MSTraceID::segment_start_times].
+    pub fn segment_arrays_f64(&self) -> Vec<Vec<f64>> {
+        self.segments().map(|seg| seg.to_vec_f64()).collect()
+    }
+    /// Samples of each segment as f32, one array per segment
+    pub fn segment_arrays_f32(&self) -> Vec<Vec<f32>> {
+        self.segments().map(|seg| seg.to_vec_f32()).collect()
+    }
+    /// Samples of each segment as i32, one array per segment
+    pub fn segment_arrays_i32(&self) -> Vec<Vec<i32>> {
+        self.segments().map(|seg| seg.to_vec_i32()).collect()
+    }
+    /// Start time of each segment
+    pub fn segment_start_times(&self) -> Vec<time::OffsetDateTime> {
+        self.segments().map(|seg| seg.start_time()).collect()
+    }
+    // Merge adjacent segments, see MSTraceList::merge
+    fn merge_within(&self, tolerance: f64) {
+        unsafe {
+            let id = &mut *self.0;
+            let mut seg = id.first;
+            while !seg.is_null() && !(*seg).next.is_null() {
+                let next = (*seg).next;
+                if segments_adjacent(&*seg, &*next, tolerance)
+                    && merge_segment_data(&mut *seg, &*next)
+                {
+                    (*seg).endtime = (*next).endtime;
+                    (*seg).samplecnt += (*next).samplecnt;
+                    (*seg).next = (*next).next;
+                    if (*next).next.is_null() {
+                        id.last = seg;
+                    } else {
+                        (*(*next).next).prev = seg;
+                    }
+                    merge_record_lists(&mut *seg, &mut *next);
+                    ms_free((*next).datasamples);
+                    ms_free(next as *mut c_void);
+                    id.numsegments -= 1;
+                } else {
+                    seg = next;
+                }
+            }
+        }
+    }
+}
+
+// Sample rate in Hz, negative values are a sample period in seconds
+fn samprate_hz(samprate: f64) -> f64 {
+    if samprate < 0.0 {
+        -1.0 / samprate
+    } else {
+        samprate
+    }
+}
+
+// Empty files, or too short to contain a record, are treated as having no records
+fn too_short(len: u64) -> bool {
+    len < libmseed_sys::MINRECLEN as u64
+}
+
+fn file_too_short(path: &str) -> bool {
+    match std::fs::metadata(path) {
+        Ok(m) => m.is_file() && too_short(m.len()),
+        Err(_) => false,
+    }
+}
+
+// Nearest i32 if `x` is within `eps`, relative to its magnitude, of a whole number
+fn whole_i32(x: f64, eps: f64) -> Option<i32> {
+    let r = x.round();
+    if (x - r).abs() <= eps * x.abs().max(1.0) && r >= i32::MIN as f64 && r <= i32::MAX as f64 {
+        Some(r as i32)
+    } else {
+        None
+    }
+}
+
+// Number of samples missing between the end of `a` and the start of `b`
+fn missing_samples(a: &MS3TraceSeg, b: &MS3TraceSeg) -> usize {
+    let gap = (b.starttime - a.endtime) as f64 / libmseed_sys::NSTMODULUS as f64;
+    let n = (gap * samprate_hz(a.samprate)).round() - 1.0;
+    if n.is_finite() && n > 0.0 {
+        n as usize
+    } else {
+        0
+    }
+}
+
+// Sample rates within the tolerance used by libmseed, MS_ISRATETOLERABLE
+pub(crate) fn rate_tolerable(a: f64, b: f64) -> bool {
+    (1.0 - a / b).abs() < 0.0001
+}
+
+fn segments_adjacent(a: &MS3TraceSeg, b: &MS3TraceSeg, tolerance: f64) -> bool {
+    let (ra, rb) = (samprate_hz(a.samprate), samprate_hz(b.samprate));
+    if ra <= 0.0 || rb <= 0.0 || !rate_tolerable(ra, rb) {
+        return false;
+    }
+    let gap = (b.starttime - a.endtime) as f64 / libmseed_sys::NSTMODULUS as f64 - 1.0 / ra;
+    gap.abs() <= tolerance
+}
+
+// Trace list memory is allocated with libmseed's configurable allocator
+unsafe fn ms_realloc(p: *mut c_void, size: usize) -> *mut c_void {
+    match libmseed_sys::libmseed_memory.realloc {
+        Some(realloc) => realloc(p, size as _),
+        None => ptr::null_mut(),
+    }
+}
+
+unsafe fn ms_free(p: *mut c_void) {
+    if let Some(free) = libmseed_sys::libmseed_memory.free {
+        free(p);
+    }
+}
+
+// Move the record list of b onto the end of a
+unsafe fn merge_record_lists(a: &mut MS3TraceSeg, b: &mut MS3TraceSeg) {
+    if b.recordlist.is_null() {
+        return;
+    }
+    if a.recordlist.is_null() {
+        a.recordlist = b.recordlist;
+    } else {
+        let (ra, rb) = (&mut *a.recordlist, &*b.recordlist);
+        if ra.last.is_null() {
+            ra.first = rb.first;
+        } else {
+            (*ra.last).next = rb.first;
+        }
+        if !rb.last.is_null() {
+            ra.last = rb.last;
+        }
+        ra.recordcnt += rb.recordcnt;
+        ms_free(b.recordlist as *mut c_void);
+    }
+    b.recordlist = ptr::null_mut();
+}
+
+// Append the data samples of b onto a
+unsafe fn merge_segment_data(a: &mut MS3TraceSeg, b: &MS3TraceSeg) -> bool {
+    if a.numsamples == 0 && b.numsamples == 0 {
+        return true;
+    }
+    if a.sampletype != b.sampletype || a.numsamples != a.samplecnt || b.numsamples != b.samplecnt {
+        return false;
+    }
+    let size = match MSSampleType::try_from(a.sampletype) {
+        Ok(t) => t.sample_size(),
+        Err(_) => return false,
+    };
+    let na = a.numsamples as usize * size;
+    let nb = b.numsamples as usize * size;
+    let p = ms_realloc(a.datasamples, na + nb);
+    if p.is_null() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(b.datasamples as *const u8, (p as *mut u8).add(na), nb);
+    a.datasamples = p;
+    a.datasize = (na + nb) as _;
+    a.numsamples += b.numsamples;
+    true
 }
 
-impl Iterator for MSTraceIDIterator {
-    type Item = MSTraceID;
+impl<'a> Iterator for MSTraceIDIterator<'a> {
+    type Item = MSTraceID<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         if (*self).mstid == ptr::null_mut() {
             None
         } else {
             let prev = self.mstid;
             self.mstid = unsafe { (*self.mstid).next };
-            Some(MSTraceID(prev))
+            Some(MSTraceID(prev, PhantomData))
         }
     }
 }
 
-impl Iterator for MSTraceSegmentIterator {
-    type Item = MSTraceSegment;
+impl<'a> Iterator for MSRecordPtrIterator<'a> {
+    type Item = MSRecordPtr<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.recptr.is_null() {
+            None
+        } else {
+            let prev = self.recptr;
+            self.recptr = unsafe { (*self.recptr).next };
+            Some(MSRecordPtr(prev, PhantomData))
+        }
+    }
+}
+
+impl<'a> MSRecordPtr<'a> {
+    fn ptr(&self) -> MS3RecordPtr {
+        unsafe { *self.0 }
+    }
+    /// Record header, data samples and the raw record are not available
+    ///
+    /// The record is borrowed from the trace list.
+    pub fn record(&self) -> &'a MSRecord {
+        unsafe { &*(&(*self.0).msr as *const *mut MS3Record as *const MSRecord) }
+    }
+    pub fn filename(&self) -> Option<String> {
+        let p = self.ptr().filename;
+        if p.is_null() {
+            None
+        } else {
+            let s = unsafe { std::ffi::CStr::from_ptr(p) };
+            Some(s.to_string_lossy().into_owned())
+        }
+    }
+    /// Byte offset of the record within the file or buffer
+    pub fn offset(&self) -> i64 {
+        self.ptr().fileoffset
+    }
+    pub fn start_time(&self) -> time::OffsetDateTime {
+        self.record().start_time()
+    }
+    pub fn end_time(&self) -> time::OffsetDateTime {
+        nstime_to_time(self.ptr().endtime)
+    }
+}
+
+impl<'a> Iterator for MSTraceSegmentIterator<'a> {
+    type Item = MSTraceSegment<'a>;
     fn next(&mut self) -> Option<Self::Item> {
         if self.mstseg == ptr::null_mut() {
             None
         } else {
             let prev = self.mstseg;
             self.mstseg = unsafe { (*self.mstseg).next };
-            Some(MSTraceSegment(prev))
+            Some(MSTraceSegment(prev, self.mstid, PhantomData))
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Hash)]
+pub enum MSEncoding {
+    Text,
+    Integer16,
+    Integer32,
+    Float32,
+    Float64,
+    Steim1,
+    Steim2,
+    /// Encodings which are only read by libmseed, e.g. GEOSCOPE, CDSN, SRO
+    Other(i16),
+}
+
+impl MSEncoding {
+    pub fn from_code(code: i16) -> MSEncoding {
+        match code as u32 {
+            libmseed_sys::DE_TEXT => MSEncoding::Text,
+            libmseed_sys::DE_INT16 => MSEncoding::Integer16,
+            libmseed_sys::DE_INT32 => MSEncoding::Integer32,
+            libmseed_sys::DE_FLOAT32 => MSEncoding::Float32,
+            libmseed_sys::DE_FLOAT64 => MSEncoding::Float64,
+            libmseed_sys::DE_STEIM1 => MSEncoding::Steim1,
+            libmseed_sys::DE_STEIM2 => MSEncoding::Steim2,
+            _ => MSEncoding::Other(code),
         }
     }
+    /// Encoding code passed to libmseed, an error if out of range
+    pub fn as_i8(&self) -> Result<i8, MSError> {
+        let e = match self {
+            MSEncoding::Text => libmseed_sys::DE_TEXT,
+            MSEncoding::Integer16 => libmseed_sys::DE_INT16,
+            MSEncoding::Integer32 => libmseed_sys::DE_INT32,
+            MSEncoding::Float32 => libmseed_sys::DE_FLOAT32,
+            MSEncoding::Float64 => libmseed_sys::DE_FLOAT64,
+            MSEncoding::Steim1 => libmseed_sys::DE_STEIM1,
+            MSEncoding::Steim2 => libmseed_sys::DE_STEIM2,
+            MSEncoding::Other(code) => {
+                return i8::try_from(*code)
+                    .map_err(|_| MSError::Error(format!("Error: encoding {} out of range", code)))
+            }
+        };
+        Ok(e as i8)
+    }
 }
 
 impl MSSampleType {
@@ -165,22 +1007,39 @@ impl MSSampleType {
             MSSampleType::Integer32 => 'i' as i8,
             MSSampleType::Float32 => 'f' as i8,
             MSSampleType::Float64 => 'd' as i8,
+            MSSampleType::Text => 'a' as i8,
+        }
+    }
+    /// Size of a single sample in bytes
+    pub fn sample_size(&self) -> usize {
+        match self {
+            MSSampleType::Integer32 | MSSampleType::Float32 => 4,
+            MSSampleType::Float64 => 8,
+            MSSampleType::Text => 1,
+        }
+    }
+}
+
+impl TryFrom<std::os::raw::c_char> for MSSampleType {
+    type Error = MSError;
+    /// Sample type of a libmseed sample type character
+    fn try_from(c: std::os::raw::c_char) -> Result<Self, Self::Error> {
+        match c as u8 {
+            b'i' => Ok(MSSampleType::Integer32),
+            b'f' => Ok(MSSampleType::Float32),
+            b'd' => Ok(MSSampleType::Float64),
+            b'a' => Ok(MSSampleType::Text),
+            _ => Err(MSError::Error(format!("Error: unknown sample type: {}", c))),
         }
     }
 }
 
-impl MSTraceSegment {
+impl<'a> MSTraceSegment<'a> {
     fn ptr(&self) -> MS3TraceSeg {
         unsafe { *self.0 }
     }
-    fn sampletype(&self) -> MSSampleType {
-        let s = self.ptr();
-        match s.sampletype {
-            105 => MSSampleType::Integer32, // i
-            102 => MSSampleType::Float32,   // f
-            100 => MSSampleType::Float64,   // d
-            _ => panic!("Unknown sample type: {}", s.sampletype),
-        }
+    pub fn sampletype(&self) -> Result<MSSampleType, MSError> {
+        MSSampleType::try_from(self.ptr().sampletype)
     }
     pub fn start_time(&self) -> time::OffsetDateTime {
         nstime_to_time(self.ptr().starttime)
@@ -188,8 +1047,16 @@ impl MSTraceSegment {
     pub fn end_time(&self) -> time::OffsetDateTime {
         nstime_to_time(self.ptr().endtime)
     }
+    /// Time of the sample immediately following the last sample of the segment
+    pub fn next_sample_time(&self) -> time::OffsetDateTime {
+        let s = self.ptr();
+        nstime_to_time(unsafe {
+            libmseed_sys::ms_sampletime(s.endtime, 1, samprate_hz(s.samprate))
+        })
+    }
+    /// Sample rate in Hz, a sample period stored as a negative value is converted
     pub fn samprate(&self) -> f64 {
-        self.ptr().samprate
+        samprate_hz(self.ptr().samprate)
     }
     pub fn samplecnt(&self) -> i64 {
         self.ptr().samplecnt
@@ -203,95 +1070,1001 @@ impl MSTraceSegment {
     pub fn data_unpacked(&self) -> bool {
         self.samplecnt() == self.numsamples() && self.datasize() > 0
     }
+    /// Records contributing to this segment
+    ///
+    /// Only available if the record list was retained, see [MSTraceList::record_list]
+    pub fn records(&self) -> MSRecordPtrIterator<'a> {
+        let list = self.ptr().recordlist;
+        let recptr = if list.is_null() {
+            ptr::null_mut()
+        } else {
+            unsafe { (*list).first }
+        };
+        MSRecordPtrIterator {
+            recptr,
+            _mstl: PhantomData,
+        }
+    }
 
-    fn convert_data(&self, t: MSSampleType) -> bool {
+    // Convert the stored samples to sample type `t` in place, see MSTraceList::convert
+    //
+    // libmseed frees and reallocates the samples, so no samples may be borrowed
+    // from the segment while converting.
+    fn convert(&self, t: MSSampleType) -> Result<(), MSError> {
         if !self.data_unpacked() {
-            return false;
+            return Err(MSError::Error("Error: data not unpacked".into()));
         }
         let truncate = 0;
-        if t != self.sampletype() {
-            unsafe { libmseed_sys::mstl3_convertsamples(self.0, t.as_char(), truncate) == 0 }
-        } else {
-            true
+        let from = self.sampletype()?;
+        if t == from {
+            return Ok(());
+        }
+        if t == MSSampleType::Text || from == MSSampleType::Text {
+            return Err(MSError::Error(format!(
+                "Error: cannot convert {:?} samples to {:?}",
+                from, t
+            )));
         }
+        check(unsafe { libmseed_sys::mstl3_convertsamples(self.0, t.as_char(), truncate) })
     }
+    /// Samples as i32, empty if float samples cannot be converted, see [MSTraceSegment::samples_i32]
     pub fn to_vec_i32(&self) -> Vec<i32> {
+        self.samples_i32().map(Cow::into_owned).unwrap_or_default()
+    }
+    /// Integer samples as i16, e.g. from Integer16 encoded records
+    ///
+    /// libmseed has no 16-bit sample type, all integer encodings including
+    /// Integer16 are decoded to Integer32, so samples cannot be borrowed as i16
+    /// and a copy is returned.  None for non-integer data or if any sample is
+    /// out of the range of an i16, empty if the data is not unpacked.
+    pub fn to_vec_i16(&self) -> Option<Vec<i16>> {
         if !self.data_unpacked() {
-            return vec![];
+            return Some(vec![]);
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => unsafe { self.raw::<i32>() }
+                .iter()
+                .map(|x| i16::try_from(*x).ok())
+                .collect(),
+            _ => None,
         }
-        self.convert_data(MSSampleType::Integer32);
-        let s = self.ptr();
-        unsafe { from_raw_parts(s.datasamples as *mut i32, s.samplecnt as usize) }.to_vec()
     }
     pub fn to_vec_f32(&self) -> Vec<f32> {
+        self.samples_f32().into_owned()
+    }
+    pub fn to_vec_f64(&self) -> Vec<f64> {
+        self.samples_f64().into_owned()
+    }
+    // Caller must check data_unpacked() and the sample type
+    // Returns an empty slice if numsamples exceeds the data buffer
+    unsafe fn raw<T>(&self) -> &[T] {
+        let s = self.ptr();
+        let n = s.numsamples.max(0) as usize;
+        match n.checked_mul(std::mem::size_of::<T>()) {
+            Some(size) if !s.datasamples.is_null() && size as u64 <= s.datasize => {
+                from_raw_parts(s.datasamples as *const T, n)
+            }
+            _ => &[],
+        }
+    }
+    /// Samples as i32, borrowed if stored as Integer32
+    ///
+    /// Float samples are rounded if within the machine epsilon of a whole
+    /// number, see [MSTraceSegment::is_integer_valued], None if any sample is
+    /// not a whole number within the range of an i32.  Empty for text or data
+    /// not unpacked.
+    pub fn samples_i32(&self) -> Option<Cow<'_, [i32]>> {
         if !self.data_unpacked() {
-            return vec![];
+            return Some(Cow::Borrowed(&[]));
+        }
+        fn to_i32<T: Copy + Into<f64>>(v: &[T], eps: f64) -> Option<Vec<i32>> {
+            v.iter().map(|x| whole_i32((*x).into(), eps)).collect()
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => Some(Cow::Borrowed(unsafe { self.raw::<i32>() })),
+            Ok(MSSampleType::Float32) => {
+                to_i32(unsafe { self.raw::<f32>() }, f32::EPSILON as f64).map(Cow::Owned)
+            }
+            Ok(MSSampleType::Float64) => {
+                to_i32(unsafe { self.raw::<f64>() }, f64::EPSILON).map(Cow::Owned)
+            }
+            Ok(MSSampleType::Text) | Err(_) => Some(Cow::Borrowed(&[])),
         }
-        self.convert_data(MSSampleType::Float32);
-        let s = self.ptr();
-        unsafe { from_raw_parts(s.datasamples as *mut f32, s.samplecnt as usize) }.to_vec()
     }
-    pub fn to_vec_f64(&self) -> Vec<f64> {
+    pub fn samples_f32(&self) -> Cow<'_, [f32]> {
         if !self.data_unpacked() {
-            return vec![];
+            return Cow::Borrowed(&[]);
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => Cow::Owned(
+                unsafe { self.raw::<i32>() }
+                    .iter()
+                    .map(|x| *x as f32)
+                    .collect(),
+            ),
+            Ok(MSSampleType::Float32) => Cow::Borrowed(unsafe { self.raw::<f32>() }),
+            Ok(MSSampleType::Float64) => Cow::Owned(
+                unsafe { self.raw::<f64>() }
+                    .iter()
+                    .map(|x| *x as f32)
+                    .collect(),
+            ),
+            Ok(MSSampleType::Text) | Err(_) => Cow::Borrowed(&[]),
         }
-        self.convert_data(MSSampleType::Float64);
-        let s = self.ptr();
-        unsafe { from_raw_parts(s.datasamples as *mut f64, s.samplecnt as usize) }.to_vec()
     }
-}
-struct NSLC {
-    net: String,
-    sta: String,
-    loc: String,
-    cha: String,
-}
-
-fn sid_to_nslc(sid: &[i8]) -> NSLC {
-    let s0 = "               ";
-    let sid = CString::new(i8_to_string(sid)).unwrap().into_raw();
-    let xnet = CString::new(s0).unwrap().into_raw();
-    let xsta = CString::new(s0).unwrap().into_raw();
-    let xloc = CString::new(s0).unwrap().into_raw();
-    let xcha = CString::new(s0).unwrap().into_raw();
-    unsafe {
-        libmseed_sys::ms_sid2nslc(sid, xnet, xsta, xloc, xcha);
-        let net = CString::from_raw(xnet).into_string().unwrap();
-        let sta = CString::from_raw(xsta).into_string().unwrap();
-        let loc = CString::from_raw(xloc).into_string().unwrap();
-        let cha = CString::from_raw(xcha).into_string().unwrap();
-        NSLC { net, sta, loc, cha }
+    pub fn samples_f64(&self) -> Cow<'_, [f64]> {
+        if !self.data_unpacked() {
+            return Cow::Borrowed(&[]);
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => Cow::Owned(
+                unsafe { self.raw::<i32>() }
+                    .iter()
+                    .map(|x| *x as f64)
+                    .collect(),
+            ),
+            Ok(MSSampleType::Float32) => Cow::Owned(
+                unsafe { self.raw::<f32>() }
+                    .iter()
+                    .map(|x| *x as f64)
+                    .collect(),
+            ),
+            Ok(MSSampleType::Float64) => Cow::Borrowed(unsafe { self.raw::<f64>() }),
+            Ok(MSSampleType::Text) | Err(_) => Cow::Borrowed(&[]),
+        }
     }
-}
-
-fn nstime_to_time(nst: i64) -> time::OffsetDateTime {
-    let mut year = 0;
-    let mut yday = 0;
-    let mut hour = 0;
-    let mut min = 0;
-    let mut sec = 0;
-    let mut nsec = 0;
-    unsafe {
+    /// Split the samples into windows of `window_secs`, each starting
+    /// `window_secs - overlap_secs` after the previous window
+    ///
+    /// Window lengths are rounded to whole samples.  The final window may be
+    /// shorter.  No windows are returned if the window is shorter than one
+    /// sample or the overlap is not shorter than the window.
+    pub fn windows_f64(
+        &self,
+        window_secs: f64,
+        overlap_secs: f64,
+    ) -> impl Iterator<Item = (time::OffsetDateTime, Vec<f64>)> {
+        let rate = self.samprate();
+        let n = (window_secs * rate).round();
+        let step = n - (overlap_secs.max(0.0) * rate).round();
+        let (n, step) = if n.is_finite() && n >= 1.0 && step >= 1.0 {
+            (n as usize, step as usize)
+        } else {
+            (0, 0)
+        };
+        let data = if n > 0 {
+            self.samples_f64().into_owned()
+        } else {
+            vec![]
+        };
+        let start = self.ptr().starttime;
+        let mut i = 0;
+        std::iter::from_fn(move || {
+            if i >= data.len() {
+                return None;
+            }
+            let end = (i + n).min(data.len());
+            let t = unsafe { libmseed_sys::ms_sampletime(start, i as i64, rate) };
+            let w = data[i..end].to_vec();
+            i = if end == data.len() { end } else { i + step };
+            Some((nstime_to_time(t), w))
+        })
+    }
+    /// Copy the samples, converted to f64, into `out` without allocating
+    ///
+    /// Returns the number of samples copied.  An error is returned if the data
+    /// is not unpacked, is text or `out` is shorter than the number of samples.
+    pub fn copy_into_f64(&self, out: &mut [f64]) -> Result<usize, MSError> {
+        if !self.data_unpacked() {
+            return Err(MSError::Error("Error: data not unpacked".into()));
+        }
+        fn copy<T: Copy + Into<f64>>(v: &[T], out: &mut [f64]) -> usize {
+            for (o, x) in out.iter_mut().zip(v.iter()) {
+                *o = (*x).into();
+            }
+            v.len()
+        }
+        let n = self.numsamples() as usize;
+        if out.len() < n {
+            return Err(MSError::Error(format!(
+                "Error: destination holds {} of {} samples",
+                out.len(),
+                n
+            )));
+        }
+        match self.sampletype()? {
+            MSSampleType::Integer32 => Ok(copy(unsafe { self.raw::<i32>() }, out)),
+            MSSampleType::Float32 => Ok(copy(unsafe { self.raw::<f32>() }, out)),
+            MSSampleType::Float64 => Ok(copy(unsafe { self.raw::<f64>() }, out)),
+            MSSampleType::Text => Err(MSError::Error(
+                "Error: cannot convert Text samples to Float64".into(),
+            )),
+        }
+    }
+    /// True if every sample is a whole number within the range of an i32
+    ///
+    /// Float samples are integral if within the machine epsilon of the sample
+    /// type, relative to their magnitude, of a whole number.  Text or data not
+    /// unpacked is never integer valued.
+    pub fn is_integer_valued(&self) -> bool {
+        if !self.data_unpacked() {
+            return false;
+        }
+        fn integral<T: Copy + Into<f64>>(v: &[T], eps: f64) -> bool {
+            v.iter().all(|x| whole_i32((*x).into(), eps).is_some())
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => true,
+            Ok(MSSampleType::Float32) => {
+                integral(unsafe { self.raw::<f32>() }, f32::EPSILON as f64)
+            }
+            Ok(MSSampleType::Float64) => integral(unsafe { self.raw::<f64>() }, f64::EPSILON),
+            Ok(MSSampleType::Text) | Err(_) => false,
+        }
+    }
+    /// True if the peak-to-peak range of the samples is at most `threshold`
+    ///
+    /// If `constant` is false only data within `threshold` of zero counts as
+    /// flatlined, a constant non-zero offset does not.  Text, data not unpacked
+    /// or a segment without samples is never flatlined.
+    pub fn is_flatlined(&self, threshold: f64, constant: bool) -> bool {
+        if !self.data_unpacked() {
+            return false;
+        }
+        fn flat<T: Copy + Into<f64>>(v: &[T], threshold: f64, constant: bool) -> bool {
+            if v.is_empty() {
+                return false;
+            }
+            let (lo, hi) = v
+                .iter()
+                .fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| {
+                    let x: f64 = (*x).into();
+                    (lo.min(x), hi.max(x))
+                });
+            if constant {
+                hi - lo <= threshold
+            } else {
+                lo.abs() <= threshold && hi.abs() <= threshold
+            }
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => flat(unsafe { self.raw::<i32>() }, threshold, constant),
+            Ok(MSSampleType::Float32) => flat(unsafe { self.raw::<f32>() }, threshold, constant),
+            Ok(MSSampleType::Float64) => flat(unsafe { self.raw::<f64>() }, threshold, constant),
+            Ok(MSSampleType::Text) | Err(_) => false,
+        }
+    }
+    /// Root mean square of the samples, the mean is not removed
+    ///
+    /// NaN for text, data not unpacked or a segment without samples.
+    pub fn rms(&self) -> f64 {
+        let (n, sum2) = self.fold_f64((0usize, 0.0), |(n, s), x| (n + 1, s + x * x));
+        if n == 0 {
+            f64::NAN
+        } else {
+            (sum2 / n as f64).sqrt()
+        }
+    }
+    /// Largest absolute sample value
+    ///
+    /// NaN for text, data not unpacked or a segment without samples.
+    pub fn peak_amplitude(&self) -> f64 {
+        self.fold_f64(f64::NAN, |peak, x| peak.max(x.abs()))
+    }
+    /// Number of samples at or beyond `min` or `max`, e.g. the limits of a digitizer
+    ///
+    /// Only integer data is counted, 0 for other sample types or data not unpacked.
+    pub fn count_clipped(&self, min: i32, max: i32) -> u64 {
+        if !self.data_unpacked() {
+            return 0;
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => unsafe { self.raw::<i32>() }
+                .iter()
+                .filter(|x| **x <= min || **x >= max)
+                .count() as u64,
+            _ => 0,
+        }
+    }
+    /// Samples more than `threshold_sigma` standard deviations from the mean
+    /// of their neighbours, as (sample index, value)
+    ///
+    /// Neighbours are the `window / 2` samples on either side, fewer at the
+    /// ends of the segment, and exclude the sample itself.  Samples with fewer
+    /// than 2 neighbours are not checked.  Empty for text or data not unpacked.
+    pub fn detect_spikes(&self, threshold_sigma: f64, window: usize) -> Vec<(i64, f64)> {
+        if !self.data_unpacked() {
+            return vec![];
+        }
+        fn spikes<T: Copy + Into<f64>>(v: &[T], threshold: f64, half: usize) -> Vec<(i64, f64)> {
+            let mut out = vec![];
+            let at = |j: usize| -> f64 { v[j].into() };
+            // Running sums over the window [lo, hi), relative to the first
+            // sample to limit rounding
+            let x0 = if v.is_empty() { 0.0 } else { at(0) };
+            let (mut lo, mut hi) = (0, 0);
+            let (mut sum, mut sum2) = (0.0, 0.0);
+            for i in 0..v.len() {
+                while hi < (i + half + 1).min(v.len()) {
+                    let y = at(hi) - x0;
+                    sum += y;
+                    sum2 += y * y;
+                    hi += 1;
+                }
+                while lo < i.saturating_sub(half) {
+                    let y = at(lo) - x0;
+                    sum -= y;
+                    sum2 -= y * y;
+                    lo += 1;
+                }
+                let k = (hi - lo - 1) as f64;
+                if k < 2.0 {
+                    continue;
+                }
+                // Neighbours exclude the sample itself
+                let y = at(i) - x0;
+                let mean = (sum - y) / k;
+                let var = ((sum2 - y * y) / k - mean * mean).max(0.0);
+                // Rounding of the mean of constant float data is not a spike
+                let sigma = var.sqrt().max(f64::EPSILON * (mean + x0).abs());
+                if (y - mean).abs() > threshold * sigma {
+                    out.push((i as i64, at(i)));
+                }
+            }
+            out
+        }
+        let half = window / 2;
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => {
+                spikes(unsafe { self.raw::<i32>() }, threshold_sigma, half)
+            }
+            Ok(MSSampleType::Float32) => {
+                spikes(unsafe { self.raw::<f32>() }, threshold_sigma, half)
+            }
+            Ok(MSSampleType::Float64) => {
+                spikes(unsafe { self.raw::<f64>() }, threshold_sigma, half)
+            }
+            Ok(MSSampleType::Text) | Err(_) => vec![],
+        }
+    }
+    // Fold over the samples converted to f64, without allocating
+    fn fold_f64<B, F: Fn(B, f64) -> B>(&self, init: B, f: F) -> B {
+        if !self.data_unpacked() {
+            return init;
+        }
+        fn fold<T: Copy + Into<f64>, B, F: Fn(B, f64) -> B>(v: &[T], init: B, f: F) -> B {
+            v.iter().fold(init, |acc, x| f(acc, (*x).into()))
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => fold(unsafe { self.raw::<i32>() }, init, f),
+            Ok(MSSampleType::Float32) => fold(unsafe { self.raw::<f32>() }, init, f),
+            Ok(MSSampleType::Float64) => fold(unsafe { self.raw::<f64>() }, init, f),
+            Ok(MSSampleType::Text) | Err(_) => init,
+        }
+    }
+    /// Minimum and maximum sample of each of `buckets` equal divisions of the segment
+    ///
+    /// If `buckets` exceeds the number of samples each bucket is a single sample.
+    /// Empty for text or data not unpacked.
+    pub fn envelope_f64(&self, buckets: usize) -> Vec<(f64, f64)> {
+        if !self.data_unpacked() {
+            return vec![];
+        }
+        fn envelope<T: Copy + Into<f64>>(v: &[T], buckets: usize) -> Vec<(f64, f64)> {
+            let buckets = buckets.min(v.len());
+            let mut out = vec![(f64::INFINITY, f64::NEG_INFINITY); buckets];
+            for (i, x) in v.iter().enumerate() {
+                let x: f64 = (*x).into();
+                let b = &mut out[i * buckets / v.len()];
+                *b = (b.0.min(x), b.1.max(x));
+            }
+            out
+        }
+        match self.sampletype() {
+            Ok(MSSampleType::Integer32) => envelope(unsafe { self.raw::<i32>() }, buckets),
+            Ok(MSSampleType::Float32) => envelope(unsafe { self.raw::<f32>() }, buckets),
+            Ok(MSSampleType::Float64) => envelope(unsafe { self.raw::<f64>() }, buckets),
+            Ok(MSSampleType::Text) | Err(_) => vec![],
+        }
+    }
+    /// Pack the segment into miniSEED records with a new encoding
+    ///
+    /// The source id and publication version are those of the trace the
+    /// segment belongs to.  An error is returned if the samples cannot be
+    /// represented exactly in `encoding`, e.g. non-integer floats as Steim2.
+    pub fn repack(&self, encoding: MSEncoding, reclen: i32) -> Result<Vec<u8>, MSError> {
+        if !self.data_unpacked() {
+            return Err(MSError::Error("Error: data not unpacked".into()));
+        }
+        if self.1.is_null() {
+            return Err(MSError::Error(
+                "Error: segment is not part of a trace".into(),
+            ));
+        }
+        let id = MSTraceID(self.1, PhantomData);
+        let mut pp = MSPackParam::new(&id.sid(), self.start_time(), self.samprate());
+        pp.encoding(encoding);
+        pp.record_length(reclen);
+        pp.pubversion(id.pubversion());
+        let t = self.sampletype()?;
+        let lossy = || {
+            MSError::Error(format!(
+                "Error: {:?} samples cannot be represented as {:?}",
+                t, encoding
+            ))
+        };
+        match encoding {
+            MSEncoding::Text => pp.pack_text(&self.text().ok_or_else(lossy)?),
+            MSEncoding::Integer16
+            | MSEncoding::Integer32
+            | MSEncoding::Steim1
+            | MSEncoding::Steim2 => {
+                let v = self.samples_i32().ok_or_else(lossy)?;
+                if v.is_empty() && self.samplecnt() > 0 {
+                    return Err(lossy());
+                }
+                if encoding == MSEncoding::Integer16
+                    && v.iter()
+                        .any(|x| !(i16::MIN as i32..=i16::MAX as i32).contains(x))
+                {
+                    return Err(lossy());
+                }
+                pp.pack_i32(&v)
+            }
+            MSEncoding::Float32 => {
+                let v = self.samples_f32();
+                if v.is_empty() && self.samplecnt() > 0 {
+                    return Err(lossy());
+                }
+                if t == MSSampleType::Float64 || t == MSSampleType::Integer32 {
+                    let exact = self
+                        .samples_f64()
+                        .iter()
+                        .zip(v.iter())
+                        .all(|(a, b)| *a == *b as f64);
+                    if !exact {
+                        return Err(lossy());
+                    }
+                }
+                pp.pack_f32(&v)
+            }
+            MSEncoding::Float64 => {
+                let v = self.samples_f64();
+                if v.is_empty() && self.samplecnt() > 0 {
+                    return Err(lossy());
+                }
+                pp.pack_f64(&v)
+            }
+            MSEncoding::Other(_) => Err(MSError::Error(format!(
+                "Error: packing {:?} is not supported",
+                encoding
+            ))),
+        }
+    }
+    /// Text samples, one byte per sample, for segments of type Text
+    pub fn text(&self) -> Option<String> {
+        if !self.data_unpacked() || self.sampletype().ok() != Some(MSSampleType::Text) {
+            return None;
+        }
+        let v = unsafe { self.raw::<u8>() };
+        Some(String::from_utf8_lossy(v).into_owned())
+    }
+}
+struct NSLC {
+    net: String,
+    sta: String,
+    loc: String,
+    cha: String,
+}
+
+impl From<NSLC> for SourceId {
+    fn from(x: NSLC) -> Self {
+        SourceId {
+            network: x.net,
+            station: x.sta,
+            location: x.loc,
+            channel: x.cha,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.network, self.station, self.location, self.channel
+        )
+    }
+}
+
+fn sid_to_nslc(sid: &[i8]) -> NSLC {
+    // Null terminated copy of the source id, output buffers as long as the source id
+    let mut xsid = [0i8; libmseed_sys::LM_SIDLEN as usize + 1];
+    for (x, s) in xsid
+        .iter_mut()
+        .zip(sid.iter().take(libmseed_sys::LM_SIDLEN as usize))
+    {
+        *x = *s;
+    }
+    let mut xnet = [0i8; libmseed_sys::LM_SIDLEN as usize];
+    let mut xsta = [0i8; libmseed_sys::LM_SIDLEN as usize];
+    let mut xloc = [0i8; libmseed_sys::LM_SIDLEN as usize];
+    let mut xcha = [0i8; libmseed_sys::LM_SIDLEN as usize];
+    let rv = unsafe {
+        libmseed_sys::ms_sid2nslc(
+            xsid.as_mut_ptr(),
+            xnet.as_mut_ptr(),
+            xsta.as_mut_ptr(),
+            xloc.as_mut_ptr(),
+            xcha.as_mut_ptr(),
+        )
+    };
+    if check(rv).is_err() {
+        return NSLC {
+            net: String::new(),
+            sta: String::new(),
+            loc: String::new(),
+            cha: String::new(),
+        };
+    }
+    NSLC {
+        net: i8_to_string(&xnet),
+        sta: i8_to_string(&xsta),
+        loc: i8_to_string(&xloc),
+        cha: i8_to_string(&xcha),
+    }
+}
+
+impl SourceId {
+    /// Split an FDSN source id, e.g. `FDSN:IU_ANMO_00_B_H_Z`
+    ///
+    /// Channel codes of single character band, source and subsource codes are
+    /// returned in the 3 character SEED 2 form, e.g. `BHZ`.
+    pub fn from_sid(sid: &str) -> Result<SourceId, MSError> {
+        let mut xsid = [0i8; libmseed_sys::LM_SIDLEN as usize];
+        string_to_i8(&mut xsid, sid)?;
+        let id: SourceId = sid_to_nslc(&xsid).into();
+        if id.network.is_empty() && id.station.is_empty() {
+            return Err(MSError::Error(format!("Error: invalid source id: {}", sid)));
+        }
+        Ok(id)
+    }
+    /// FDSN source id, a 3 character SEED 2 channel code is split into band,
+    /// source and subsource codes
+    pub fn to_sid(&self) -> Result<String, MSError> {
+        let cstr = |x: &str| {
+            CString::new(x).map_err(|_| MSError::Error(format!("Error: invalid code: {}", x)))
+        };
+        let net = cstr(&self.network)?;
+        let sta = cstr(&self.station)?;
+        let loc = cstr(&self.location)?;
+        let cha = cstr(&self.channel)?;
+        let mut sid = [0i8; libmseed_sys::LM_SIDLEN as usize];
+        let rv = unsafe {
+            libmseed_sys::ms_nslc2sid(
+                sid.as_mut_ptr(),
+                sid.len() as _,
+                0,
+                net.as_ptr() as *mut _,
+                sta.as_ptr() as *mut _,
+                loc.as_ptr() as *mut _,
+                cha.as_ptr() as *mut _,
+            )
+        };
+        if rv < 0 {
+            return Err(MSError::Error(format!(
+                "Error: invalid source id: {}",
+                self
+            )));
+        }
+        Ok(i8_to_string(&sid))
+    }
+}
+
+/// Samples and sample times of a single channel of a file
+///
+/// `channel` is an FDSN source id, e.g. `FDSN:IU_ANMO_00_B_H_Z`, or the
+/// network, station, location and channel codes, e.g. `IU_ANMO_00_BHZ`.
+/// Only records of the channel are read, see [MSTraceList::selections].
+/// Samples of all segments are concatenated, and the time of each sample is
+/// computed from the start time and sample rate of its segment, so gaps are
+/// only seen in the times.  Returns an error if there is no numeric data for
+/// the channel.
+pub fn read_channel_f64<S: AsRef<Path>>(
+    file: S,
+    channel: &str,
+) -> Result<(Vec<time::OffsetDateTime>, Vec<f64>), MSError> {
+    let sid = if channel.contains(':') {
+        channel.to_string()
+    } else {
+        format!("FDSN:{}", channel)
+    };
+    let sid = SourceId::from_sid(&sid)?.to_sid()?;
+    let mut sel = Selections::new();
+    sel.add(&sid, None, None, 0)?;
+    let mut mstl = MSTraceList::new(file);
+    mstl.selections(Some(sel));
+    mstl.read()?;
+    let mut times = vec![];
+    let mut values = vec![];
+    for (_, seg) in mstl.all_segments() {
+        let s = seg.ptr();
+        let samples = seg.samples_f64();
+        times.extend((0..samples.len()).map(|i| {
+            let t = unsafe {
+                libmseed_sys::ms_sampletime(s.starttime, i as i64, samprate_hz(s.samprate))
+            };
+            nstime_to_time(t)
+        }));
+        values.extend_from_slice(&samples);
+    }
+    if values.is_empty() {
+        return Err(MSError::Error(format!("Error: no data for {}", sid)));
+    }
+    Ok((times, values))
+}
+
+/// SEED 2 channel code of an FDSN channel code, e.g. `B_H_Z` to `BHZ`
+///
+/// None if the band, source and subsource codes are not single characters.
+pub fn channel_to_seed2(channel: &str) -> Option<String> {
+    let parts: Vec<&str> = channel.split('_').collect();
+    if parts.len() == 3 && parts.iter().all(|p| p.chars().count() == 1) {
+        Some(parts.concat())
+    } else {
+        None
+    }
+}
+
+/// FDSN channel code of a SEED 2 channel code, e.g. `BHZ` to `B_H_Z`
+///
+/// None if the channel code is not 3 characters.
+pub fn channel_from_seed2(channel: &str) -> Option<String> {
+    let c: Vec<char> = channel.chars().collect();
+    if c.len() == 3 && !c.contains(&'_') {
+        Some(format!("{}_{}_{}", c[0], c[1], c[2]))
+    } else {
+        None
+    }
+}
+
+fn error_string(rv: i32) -> String {
+    let msg = unsafe { libmseed_sys::ms_errorstr(rv) };
+    if msg.is_null() {
+        format!("Error: {}", rv)
+    } else {
+        let msg = unsafe { std::ffi::CStr::from_ptr(msg) };
+        format!("Error: {} ({})", msg.to_string_lossy(), rv)
+    }
+}
+
+// Result from a libmseed return code
+fn check(rv: i32) -> Result<(), MSError> {
+    match rv {
+        MS_NOERROR => Ok(()),
+        MS_ENDOFFILE => Err(MSError::EOF),
+        _ => Err(MSError::Error(error_string(rv))),
+    }
+}
+
+// Result from a libmseed return value that is a count or a negative error code
+fn check_count<T: Into<i64>>(rv: T) -> Result<i64, MSError> {
+    let rv: i64 = rv.into();
+    if rv < 0 {
+        Err(MSError::Error(error_string(rv as i32)))
+    } else {
+        Ok(rv)
+    }
+}
+
+fn io_error(e: std::io::Error) -> MSError {
+    MSError::Error(format!("Error: {}", e))
+}
+
+// Whole contents of an already open file
+fn read_file(mut file: File) -> Result<Vec<u8>, MSError> {
+    let mut buffer = vec![];
+    file.read_to_end(&mut buffer).map_err(io_error)?;
+    Ok(buffer)
+}
+
+fn path_to_cstring(path: &str) -> Result<CString, MSError> {
+    CString::new(path).map_err(|_| MSError::Error(format!("Error: invalid path: {}", path)))
+}
+
+fn nstime_to_time(nst: i64) -> time::OffsetDateTime {
+    let mut year = 0;
+    let mut yday = 0;
+    let mut hour = 0;
+    let mut min = 0;
+    let mut sec = 0;
+    let mut nsec = 0;
+    let rv = unsafe {
         libmseed_sys::ms_nstime2time(
             nst, &mut year, &mut yday, &mut hour, &mut min, &mut sec, &mut nsec,
-        );
+        )
+    };
+    let date = time::Date::try_from_yo(year.into(), yday);
+    let time = time::Time::try_from_hms_nano(hour, min, sec, nsec);
+    match (check(rv), date, time) {
+        (Ok(()), Ok(date), Ok(time)) => time::PrimitiveDateTime::new(date, time).assume_utc(),
+        // nstime is nanoseconds since the epoch
+        _ => time::OffsetDateTime::unix_epoch() + time::Duration::nanoseconds(nst),
+    }
+}
+
+/// Nanoseconds since the UNIX epoch, libmseed time is already in these units
+pub fn nstime_to_epoch_nanos(nst: i64) -> i64 {
+    nst
+}
+
+/// libmseed time from nanoseconds since the UNIX epoch
+pub fn epoch_nanos_to_nstime(nanos: i64) -> i64 {
+    nanos
+}
+
+fn time_to_nstime(t: time::OffsetDateTime) -> i64 {
+    let t = t.to_offset(time::UtcOffset::UTC);
+    unsafe {
+        libmseed_sys::ms_time2nstime(
+            t.year(),
+            t.ordinal() as _,
+            t.hour() as _,
+            t.minute() as _,
+            t.second() as _,
+            t.nanosecond(),
+        )
+    }
+}
+
+/// Sample rate in Hz of a SEED 2 sample rate factor and multiplier
+///
+/// Positive values multiply, negative values divide, e.g. `(-100, 1)` is 0.01 Hz.
+pub fn factor_mult_to_samprate(factor: i16, mult: i16) -> f64 {
+    let (f, m) = (factor as f64, mult as f64);
+    match (factor > 0, mult > 0) {
+        _ if factor == 0 || mult == 0 => 0.0,
+        (true, true) => f * m,
+        (true, false) => -f / m,
+        (false, true) => -m / f,
+        (false, false) => 1.0 / (f * m),
+    }
+}
+
+/// Publication version of a miniSEED2 data quality indicator, following libmseed
+///
+/// R: 1, D: 2, Q: 3, M: 4, any other indicator is None.
+pub fn quality_to_pubversion(quality: char) -> Option<u8> {
+    match quality {
+        'R' => Some(1),
+        'D' => Some(2),
+        'Q' => Some(3),
+        'M' => Some(4),
+        _ => None,
+    }
+}
+
+/// miniSEED2 data quality indicator of a publication version, following libmseed
+///
+/// 1: R, 2: D, 3: Q and 4 or greater: M, 0 is D.
+pub fn pubversion_to_quality(pubversion: u8) -> char {
+    match pubversion {
+        1 => 'R',
+        2 => 'D',
+        3 => 'Q',
+        0 => 'D',
+        _ => 'M',
+    }
+}
+
+// 64-bit FNV-1a, fixed so hashes are reproducible across versions
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Self {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn string_to_i8(dst: &mut [i8], src: &str) -> Result<(), MSError> {
+    // Leave room for the null terminator
+    if src.len() >= dst.len() {
+        return Err(MSError::Error(format!("String too long: {}", src)));
     }
-    let date = time::Date::try_from_yo(year.into(), yday).unwrap();
-    let time = time::Time::try_from_hms_nano(hour, min, sec, nsec).unwrap();
-    let t = time::PrimitiveDateTime::new(date, time);
-    t.assume_utc()
+    for x in dst.iter_mut() {
+        *x = 0;
+    }
+    for (x, b) in dst.iter_mut().zip(src.bytes()) {
+        *x = b as i8;
+    }
+    Ok(())
 }
 
 impl MSRecord {
+    // Take ownership of a parsed record, keeping a copy of the raw record
+    //
+    // The buffer the record was parsed from is reused or freed by the next
+    // read, the copy is freed with the record.
+    fn owned(msr: *mut MS3Record) -> MSRecord {
+        let m = unsafe { &mut *msr };
+        if !m.record.is_null() && m.reclen > 0 {
+            let raw = unsafe { from_raw_parts(m.record as *const u8, m.reclen as usize) };
+            m.record = Box::into_raw(raw.to_vec().into_boxed_slice()) as *mut u8 as *const _;
+        } else {
+            m.record = ptr::null();
+        }
+        MSRecord(msr)
+    }
+    // Take ownership of a parsed record without the raw record
+    fn header_only(msr: *mut MS3Record) -> MSRecord {
+        unsafe { (*msr).record = ptr::null() };
+        MSRecord(msr)
+    }
     fn ptr(&self) -> MS3Record {
         unsafe { *self.0 }
     }
     pub fn numsamples(&self) -> i64 {
         self.ptr().numsamples
     }
+    pub fn samplecnt(&self) -> i64 {
+        self.ptr().samplecnt
+    }
+    pub fn datasize(&self) -> u64 {
+        self.ptr().datasize
+    }
+    /// Sample rate in Hz, a sample period stored as a negative value is converted
+    pub fn samprate(&self) -> f64 {
+        samprate_hz(self.ptr().samprate)
+    }
+    /// Sample rate as a SEED 2 factor and multiplier, see [factor_mult_to_samprate]
+    ///
+    /// libmseed does not retain the values from a miniSEED2 header, they are
+    /// generated from the sample rate.  None if the rate cannot be represented.
+    pub fn sample_rate_factor_mult(&self) -> Option<(i16, i16)> {
+        let mut factor: i16 = 0;
+        let mut mult: i16 = 0;
+        let rv = unsafe { libmseed_sys::ms_genfactmult(self.samprate(), &mut factor, &mut mult) };
+        if rv == 0 {
+            Some((factor, mult))
+        } else {
+            None
+        }
+    }
+    pub fn reclen(&self) -> i32 {
+        self.ptr().reclen
+    }
+    pub fn encoding(&self) -> MSEncoding {
+        MSEncoding::from_code(self.ptr().encoding)
+    }
+    pub fn pubversion(&self) -> u8 {
+        self.ptr().pubversion
+    }
+    /// miniSEED2 data quality indicator, derived from the publication version
+    ///
+    /// libmseed stores the miniSEED2 quality as a publication version,
+    /// R: 1, D: 2, Q: 3, M: 4, see [pubversion_to_quality].
+    pub fn data_quality(&self) -> char {
+        pubversion_to_quality(self.ptr().pubversion)
+    }
+    pub fn is_fully_decoded(&self) -> bool {
+        self.samplecnt() == self.numsamples() && self.datasize() > 0
+    }
     pub fn sid(&self) -> String {
         i8_to_string(&(self.ptr().sid))
     }
+    pub fn extra_headers(&self) -> Option<String> {
+        let m = self.ptr();
+        if m.extra.is_null() || m.extralength == 0 {
+            return None;
+        }
+        let v = unsafe { from_raw_parts(m.extra as *const u8, m.extralength as usize) };
+        Some(String::from_utf8_lossy(v).into_owned())
+    }
+    /// Timing quality as a percentage, from the `FDSN.Time.Quality` extra header
+    ///
+    /// libmseed stores the timing quality of a miniSEED2 blockette 1001 in
+    /// this header.  The blockette 100 sample rate and the blockette 1001
+    /// microseconds are applied by libmseed to [MSRecord::samprate] and the
+    /// start time.
+    pub fn timing_quality(&self) -> Option<u8> {
+        self.extra_header_number("FDSN.Time.Quality")
+            .filter(|q| (0.0..=255.0).contains(q))
+            .map(|q| q as u8)
+    }
+    /// Time correction in seconds, from the `FDSN.Time.Correction` extra header
+    pub fn time_correction(&self) -> Option<f64> {
+        self.extra_header_number("FDSN.Time.Correction")
+    }
+    // Numeric value of an extra header, None if missing or not a number
+    fn extra_header_number(&self, path: &str) -> Option<f64> {
+        let m = self.ptr();
+        if m.extra.is_null() || m.extralength == 0 {
+            return None;
+        }
+        let path = CString::new(path).ok()?;
+        let mut value: f64 = 0.0;
+        let rv = unsafe {
+            libmseed_sys::mseh_get_ptr_r(
+                self.0,
+                path.as_ptr(),
+                &mut value as *mut f64 as *mut _,
+                b'n' as _,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if rv == 0 {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    /// Stable 64-bit FNV-1a hash of the decoded samples and key header fields
+    ///
+    /// Independent of record length, encoding and format version. Hashed in order:
+    ///  - source id as UTF-8 bytes followed by a zero byte
+    ///  - start time, nanoseconds since the epoch as i64 little endian
+    ///  - sample rate in Hz as f64 bits little endian
+    ///  - number of decoded samples as i64 little endian
+    ///  - each sample, numeric samples as f64 bits little endian and
+    ///    text as the raw bytes
+    ///
+    /// Records read without unpacking data only include the header fields and
+    /// a sample count of 0.
+    pub fn content_hash(&self) -> u64 {
+        let m = self.ptr();
+        let mut h = Fnv1a::new();
+        h.write(self.sid().as_bytes());
+        h.write(&[0]);
+        h.write(&m.starttime.to_le_bytes());
+        h.write(&self.samprate().to_bits().to_le_bytes());
+        let t = match MSSampleType::try_from(m.sampletype) {
+            Ok(t) => t,
+            Err(_) => {
+                h.write(&0i64.to_le_bytes());
+                return h.finish();
+            }
+        };
+        let n = m.numsamples;
+        let n = if m.datasamples.is_null()
+            || n < 0
+            || (n as u64).saturating_mul(t.sample_size() as u64) > m.datasize
+        {
+            0
+        } else {
+            n as usize
+        };
+        h.write(&(n as i64).to_le_bytes());
+        if n == 0 {
+            return h.finish();
+        }
+        let data = m.datasamples;
+        unsafe {
+            match t {
+                MSSampleType::Integer32 => from_raw_parts(data as *const i32, n)
+                    .iter()
+                    .for_each(|v| h.write(&(*v as f64).to_bits().to_le_bytes())),
+                MSSampleType::Float32 => from_raw_parts(data as *const f32, n)
+                    .iter()
+                    .for_each(|v| h.write(&(*v as f64).to_bits().to_le_bytes())),
+                MSSampleType::Float64 => from_raw_parts(data as *const f64, n)
+                    .iter()
+                    .for_each(|v| h.write(&v.to_bits().to_le_bytes())),
+                MSSampleType::Text => h.write(from_raw_parts(data as *const u8, n)),
+            }
+        }
+        h.finish()
+    }
     pub fn id(&self) -> String {
         let v = sid_to_nslc(&self.ptr().sid);
         format!("{}_{}_{}_{}", v.net, v.sta, v.loc, v.cha)
@@ -311,25 +2084,127 @@ impl MSRecord {
     pub fn start_time(&self) -> time::OffsetDateTime {
         nstime_to_time(self.ptr().starttime)
     }
+    pub fn header(&self) -> RecordHeader {
+        let m = self.ptr();
+        RecordHeader {
+            sid: self.sid(),
+            start_nstime: m.starttime,
+            samprate: samprate_hz(m.samprate),
+            samplecnt: m.samplecnt,
+            reclen: m.reclen,
+            encoding: m.encoding,
+            pubversion: m.pubversion,
+            flags: m.flags,
+        }
+    }
+    /// Sample rate implied by the sample count of this record and the start time of `next`
+    ///
+    /// Returns NaN if `next` does not start after this record.
+    pub fn implied_rate(&self, next: &MSRecord) -> f64 {
+        let dt = next.ptr().starttime - self.ptr().starttime;
+        if dt <= 0 {
+            return f64::NAN;
+        }
+        self.samplecnt() as f64 / (dt as f64 / libmseed_sys::NSTMODULUS as f64)
+    }
+    pub fn start_epoch_nanos(&self) -> i64 {
+        nstime_to_epoch_nanos(self.ptr().starttime)
+    }
     pub fn time_string(&self) -> String {
-        let show_subseconds = 1;
-        let time_format = libmseed_sys::ms_timeformat_t_SEEDORDINAL;
-        let time = CString::new("                                 ")
-            .unwrap()
-            .into_raw();
+        nstime_to_string(self.ptr().starttime)
+    }
+    /// Hex dump of the raw header bytes followed by the parsed header fields
+    ///
+    /// The raw bytes are only available for records read from a file, buffer
+    /// or reader, not for records of a trace list, see [MSRecordPtr::record].
+    pub fn debug_dump(&self) -> String {
+        use std::fmt::Write;
         let m = self.ptr();
-        unsafe { libmseed_sys::ms_nstime2timestr(m.starttime, time, time_format, show_subseconds) };
-        let out = unsafe { CString::from_raw(time).into_string().unwrap() };
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}, version {}, {} bytes",
+            self.sid(),
+            m.formatversion,
+            m.reclen
+        );
+        let hlen = (m.reclen as i64 - m.datalength as i64).max(0) as usize;
+        if m.record.is_null() || m.reclen <= 0 {
+            let _ = writeln!(out, "raw record not available");
+        } else {
+            let _ = writeln!(out, "header, {} bytes:", hlen);
+            let raw = unsafe { from_raw_parts(m.record as *const u8, hlen) };
+            for (i, line) in raw.chunks(16).enumerate() {
+                let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+                let text: String = line
+                    .iter()
+                    .map(|b| {
+                        if b.is_ascii_graphic() {
+                            *b as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                let _ = writeln!(out, "  {:04x}  {:<47}  {}", i * 16, hex.join(" "), text);
+            }
+        }
+        let fields: Vec<(&str, String)> = vec![
+            ("start time", self.time_string()),
+            ("sample rate", format!("{} Hz", self.samprate())),
+            ("sample count", m.samplecnt.to_string()),
+            (
+                "encoding",
+                format!("{:?} ({})", self.encoding(), m.encoding),
+            ),
+            ("publication version", m.pubversion.to_string()),
+            ("flags", format!("{:#010b}", m.flags)),
+            ("CRC", format!("{:#010x}", m.crc)),
+            ("extra header length", m.extralength.to_string()),
+            ("data length", m.datalength.to_string()),
+        ];
+        for (k, v) in fields {
+            let _ = writeln!(out, "  {}: {}", k, v);
+        }
+        if let Some(extra) = self.extra_headers() {
+            let _ = writeln!(out, "  extra headers: {}", extra);
+        }
         out
     }
 }
+fn nstime_to_string(nst: i64) -> String {
+    let show_subseconds = 1;
+    let time_format = libmseed_sys::ms_timeformat_t_SEEDORDINAL;
+    nstime_format(nst, time_format, show_subseconds)
+}
+fn nstime_format(
+    nst: i64,
+    time_format: libmseed_sys::ms_timeformat_t,
+    subseconds: libmseed_sys::ms_subseconds_t,
+) -> String {
+    // libmseed time strings are at most 36 characters plus the null terminator
+    let mut time: [std::os::raw::c_char; 37] = [0; 37];
+    let rv =
+        unsafe { libmseed_sys::ms_nstime2timestr(nst, time.as_mut_ptr(), time_format, subseconds) };
+    // Guarantee a terminator even if libmseed filled the buffer
+    time[time.len() - 1] = 0;
+    if rv.is_null() {
+        return nstime_to_time(nst).to_string();
+    }
+    let v: Vec<u8> = time
+        .iter()
+        .take_while(|x| **x != 0)
+        .map(|x| *x as u8)
+        .collect();
+    String::from_utf8_lossy(&v).into_owned()
+}
 fn i8_to_string(vin: &[i8]) -> String {
     let v: Vec<u8> = vin
         .iter()
         .map(|x| *x as u8) // cast i8 as u8
         .filter(|x| *x != 0u8) // remove null terminators
         .collect();
-    String::from_utf8(v).unwrap() // convert to  string
+    String::from_utf8_lossy(&v).into_owned() // convert to  string
 }
 
 use std::fmt;
@@ -343,31 +2218,211 @@ impl fmt::Display for MSRecord {
             v.pubversion,
             v.reclen,
             v.samplecnt,
-            v.samprate,
+            samprate_hz(v.samprate),
             self.time_string(),
             self.start_time()
         )
     }
 }
 
-impl MSFileParam {
-    pub fn new<S: AsRef<Path>>(file: S) -> MSFileParam {
-        let path: String = file.as_ref().to_string_lossy().into_owned();
-        let mspath = CString::new(path.clone()).unwrap();
-        let msfp: *mut MS3FileParam = ptr::null_mut();
-        Self {
-            path,
-            msfp,
-            mspath,
-            fpos: 0,
-            last: 0,
-            flags: libmseed_sys::MSF_UNPACKDATA,
-            verbose: 0,
-        }
-    }
-    pub fn unpack_data(&mut self, unpack: bool) {
-        if unpack {
-            self.flags |= libmseed_sys::MSF_UNPACKDATA;
+impl<'a> fmt::Display for MSTraceID<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = self.ptr();
+        let samprate = self.segments().next().map(|s| s.samprate()).unwrap_or(0.0);
+        write!(
+            f,
+            "{} {} {} {} Hz, {} segments",
+            self.sid(),
+            nstime_to_string(v.earliest),
+            nstime_to_string(v.latest),
+            samprate,
+            v.numsegments
+        )
+    }
+}
+
+// Debug output shows header fields, pointers are only dereferenced if not null
+
+impl fmt::Debug for MSRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "MSRecord(null)");
+        }
+        let v = self.ptr();
+        f.debug_struct("MSRecord")
+            .field("sid", &self.sid())
+            .field("start", &nstime_to_string(v.starttime))
+            .field("samprate", &samprate_hz(v.samprate))
+            .field("samplecnt", &v.samplecnt)
+            .field("numsamples", &v.numsamples)
+            .field("reclen", &v.reclen)
+            .field("encoding", &v.encoding)
+            .field("pubversion", &v.pubversion)
+            .finish()
+    }
+}
+
+impl fmt::Debug for MSTraceList {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut d = f.debug_struct("MSTraceList");
+        d.field("path", &self.path)
+            .field("buffer_len", &self.buffer.as_ref().map(|b| b.len()))
+            .field("flags", &self.flags)
+            .field("selections", &self.selections.is_some());
+        match self.ptr() {
+            None => d.field("traces", &format_args!("unread")),
+            Some(v) => d
+                .field("numtraces", &v.numtraces)
+                .field("records_read", &self.nread)
+                .field("records_skipped", &self.nskipped),
+        };
+        d.finish()
+    }
+}
+
+impl<'a> fmt::Debug for MSTraceID<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "MSTraceID(null)");
+        }
+        let v = self.ptr();
+        f.debug_struct("MSTraceID")
+            .field("sid", &self.sid())
+            .field("earliest", &nstime_to_string(v.earliest))
+            .field("latest", &nstime_to_string(v.latest))
+            .field("pubversion", &v.pubversion)
+            .field("numsegments", &v.numsegments)
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for MSTraceSegment<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "MSTraceSegment(null)");
+        }
+        let v = self.ptr();
+        f.debug_struct("MSTraceSegment")
+            .field("start", &nstime_to_string(v.starttime))
+            .field("end", &nstime_to_string(v.endtime))
+            .field("samprate", &samprate_hz(v.samprate))
+            .field("samplecnt", &v.samplecnt)
+            .field("numsamples", &v.numsamples)
+            .field(
+                "sampletype",
+                &if v.sampletype == 0 {
+                    '-'
+                } else {
+                    v.sampletype as u8 as char
+                },
+            )
+            .field("unpacked", &self.data_unpacked())
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for MSRecordPtr<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.is_null() {
+            return write!(f, "MSRecordPtr(null)");
+        }
+        let v = self.ptr();
+        f.debug_struct("MSRecordPtr")
+            .field("filename", &self.filename())
+            .field("offset", &v.fileoffset)
+            .field("end", &nstime_to_string(v.endtime))
+            .field("record", self.record())
+            .finish()
+    }
+}
+
+impl fmt::Debug for MSFileParam {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSFileParam")
+            .field("path", &self.path)
+            .field("offset", &self.fpos)
+            .field("flags", &self.flags)
+            .field("verbose", &self.verbose)
+            .field("record_length", &self.reclen)
+            .field("open", &(!self.msfp.is_null() || self.file.is_some()))
+            .finish()
+    }
+}
+
+// Iterators show the next item
+impl<'a> fmt::Debug for MSTraceIDIterator<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSTraceIDIterator")
+            .field("next", &MSTraceID(self.mstid, PhantomData))
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for MSTraceSegmentIterator<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSTraceSegmentIterator")
+            .field(
+                "next",
+                &MSTraceSegment(self.mstseg, self.mstid, PhantomData),
+            )
+            .finish()
+    }
+}
+
+impl<'a> fmt::Debug for MSRecordPtrIterator<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MSRecordPtrIterator")
+            .field("next", &MSRecordPtr(self.recptr, PhantomData))
+            .finish()
+    }
+}
+
+impl<'a> fmt::Display for MSTraceSegment<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let v = self.ptr();
+        let sampletype = if v.sampletype == 0 {
+            '-'
+        } else {
+            v.sampletype as u8 as char
+        };
+        write!(
+            f,
+            "{} {} {} Hz, {} samples, type {}, {}",
+            nstime_to_string(v.starttime),
+            nstime_to_string(v.endtime),
+            samprate_hz(v.samprate),
+            v.samplecnt,
+            sampletype,
+            if self.data_unpacked() {
+                "unpacked"
+            } else {
+                "not unpacked"
+            }
+        )
+    }
+}
+
+impl MSFileParam {
+    pub fn new<S: AsRef<Path>>(file: S) -> MSFileParam {
+        let path: String = file.as_ref().to_string_lossy().into_owned();
+        let mspath = CString::new(path.clone()).ok();
+        let msfp: *mut MS3FileParam = ptr::null_mut();
+        Self {
+            path,
+            msfp,
+            mspath,
+            fpos: 0,
+            last: 0,
+            flags: libmseed_sys::MSF_UNPACKDATA,
+            verbose: 0,
+            reclen: None,
+            file: None,
+            recbuf: vec![],
+        }
+    }
+    pub fn unpack_data(&mut self, unpack: bool) {
+        if unpack {
+            self.flags |= libmseed_sys::MSF_UNPACKDATA;
         } else {
             self.flags &= !libmseed_sys::MSF_UNPACKDATA;
         }
@@ -382,29 +2437,118 @@ impl MSFileParam {
     pub fn verbose(&mut self, verbose: bool) {
         self.verbose = if verbose { 1 } else { 0 };
     }
+    /// Read the file as records of a fixed length, e.g. 512 bytes
+    ///
+    /// Each record is parsed from exactly `reclen` bytes, for archives where
+    /// the record length cannot be detected.  A wrong length causes read errors.
+    /// Set the hint before reading the first record.
+    pub fn record_length(&mut self, reclen: Option<usize>) {
+        self.reclen = reclen;
+    }
     pub fn filename(&self) -> &str {
         &self.path
     }
+    /// File offset of the last record read
+    pub fn offset(&self) -> i64 {
+        self.fpos
+    }
     pub fn read_record(&mut self) -> Result<MSRecord, MSError> {
+        self.read_msr(self.flags).map(MSRecord::owned)
+    }
+    /// Reset reading to the start of the file
+    pub fn rewind(&mut self) -> Result<(), MSError> {
+        self.close()?;
+        self.fpos = 0;
+        self.last = 0;
+        self.file = None;
+        Ok(())
+    }
+    // Free the libmseed file state, the file is reopened by the next read
+    fn close(&mut self) -> Result<(), MSError> {
+        let mut msr: *mut MS3Record = ptr::null_mut();
+        let rv = unsafe {
+            libmseed_sys::ms3_readmsr_r(
+                (&mut self.msfp) as *mut *mut MS3FileParam,
+                (&mut msr) as *mut *mut MS3Record,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+                0,
+            )
+        };
+        check(rv)
+    }
+    /// Read the header of the next record, data samples are not unpacked
+    pub fn read_header(&mut self) -> Result<RecordHeader, MSError> {
+        let msr = self.read_msr(self.flags & !libmseed_sys::MSF_UNPACKDATA)?;
+        Ok(MSRecord::header_only(msr).header())
+    }
+    /// Read the header of the next record, data samples are unpacked on demand
+    ///
+    /// The returned record holds a copy of the raw record and is independent
+    /// of the file param.
+    pub fn read_lazy(&mut self) -> Result<LazyRecord, MSError> {
+        let msr = self.read_msr(self.flags & !libmseed_sys::MSF_UNPACKDATA)?;
+        // Data is unpacked from the raw record
+        let rec = MSRecord::owned(msr);
+        Ok(LazyRecord {
+            header: rec.header(),
+            rec,
+            verbose: self.verbose,
+        })
+    }
+    fn read_msr(&mut self, flags: u32) -> Result<*mut MS3Record, MSError> {
+        if let Some(reclen) = self.reclen {
+            return self.read_fixed(reclen, flags);
+        }
+        if self.msfp.is_null() && file_too_short(&self.path) {
+            return Err(MSError::EOF);
+        }
+        let mspath = match &self.mspath {
+            Some(mspath) => mspath.as_ptr(),
+            None => {
+                return Err(MSError::Error(format!(
+                    "Error: invalid path: {}",
+                    self.path
+                )))
+            }
+        };
         let mut msr: *mut MS3Record = ptr::null_mut();
         let rv = unsafe {
             libmseed_sys::ms3_readmsr_r(
                 (&mut self.msfp) as *mut *mut MS3FileParam,
                 (&mut msr) as *mut *mut MS3Record,
-                self.mspath.as_ptr(),
+                mspath,
                 &mut self.fpos,
                 &mut self.last,
-                self.flags,
+                flags,
                 self.verbose,
             )
         };
-        if rv == MS_NOERROR {
-            Ok(MSRecord(msr))
-        } else if rv == MS_ENDOFFILE {
-            Err(MSError::EOF)
-        } else {
-            Err(MSError::Error(format!("Error: {}", rv)))
+        check(rv).map(|_| msr)
+    }
+}
+
+/// Record header with data samples unpacked on demand, see [MSFileParam::read_lazy]
+#[derive(Debug)]
+pub struct LazyRecord {
+    header: RecordHeader,
+    rec: MSRecord,
+    verbose: i8,
+}
+
+impl LazyRecord {
+    pub fn header(&self) -> &RecordHeader {
+        &self.header
+    }
+    /// Unpack the data samples of the record, only unpacked once
+    pub fn unpack(&mut self) -> Result<&MSRecord, MSError> {
+        if !self.rec.is_fully_decoded() && self.rec.samplecnt() > 0 {
+            let rv = unsafe { libmseed_sys::msr3_unpack_data(self.rec.0, self.verbose) };
+            check_count(rv)?;
         }
+        Ok(&self.rec)
     }
 }
 
@@ -419,27 +2563,161 @@ impl Iterator for MSFileParam {
     }
 }
 
-impl Drop for MSFileParam {
-    fn drop(&mut self) {
+impl MSFileParam {
+    fn read_fixed(&mut self, reclen: usize, flags: u32) -> Result<*mut MS3Record, MSError> {
+        let err = |e: std::io::Error| MSError::Error(format!("Error: {}", e));
+        if self.file.is_none() {
+            self.file = Some(File::open(&self.path).map_err(err)?);
+            self.fpos = -(reclen as i64);
+        }
+        let file = self.file.as_mut().unwrap();
+        self.recbuf.resize(reclen, 0);
+        let mut n = 0;
+        while n < reclen {
+            match file.read(&mut self.recbuf[n..]).map_err(err)? {
+                0 => break,
+                k => n += k,
+            }
+        }
+        if n == 0 {
+            return Err(MSError::EOF);
+        }
+        self.fpos += reclen as i64;
+        if n < reclen {
+            return Err(MSError::Error(format!(
+                "Error: truncated record at offset {}",
+                self.fpos
+            )));
+        }
         let mut msr: *mut MS3Record = ptr::null_mut();
+        // The record is the whole buffer if its length cannot be detected,
+        // e.g. miniSEED2 without a blockette 1000
         let rv = unsafe {
-            libmseed_sys::ms3_readmsr_r(
-                (&mut self.msfp) as *mut *mut MS3FileParam,
-                (&mut msr) as *mut *mut MS3Record,
-                ptr::null_mut(),
-                ptr::null_mut(),
-                ptr::null_mut(),
-                0,
-                0,
+            libmseed_sys::msr3_parse(
+                self.recbuf.as_ptr() as *const _,
+                reclen as _,
+                &mut msr,
+                flags | libmseed_sys::MSF_ATENDOFFILE,
+                self.verbose,
             )
         };
-        assert!(rv == MS_NOERROR);
+        if rv != 0 {
+            unsafe { libmseed_sys::msr3_free(&mut msr) };
+        }
+        if rv > 0 {
+            return Err(MSError::Error(format!(
+                "Error: record at offset {} is longer than {} bytes",
+                self.fpos, reclen
+            )));
+        }
+        check(rv)?;
+        let found = unsafe { (*msr).reclen };
+        if found as usize != reclen {
+            unsafe { libmseed_sys::msr3_free(&mut msr) };
+            return Err(MSError::Error(format!(
+                "Error: record at offset {} has length {}, expected {}",
+                self.fpos, found, reclen
+            )));
+        }
+        Ok(msr)
+    }
+}
+
+impl Drop for MSTraceList {
+    fn drop(&mut self) {
+        if !self.mstl.is_null() {
+            unsafe { libmseed_sys::mstl3_free(&mut self.mstl, 0) };
+        }
+    }
+}
+
+// Records read from files and buffers are owned, records of a trace list
+// are only borrowed, see MSRecordPtr::record
+impl Drop for MSRecord {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            // Raw record copied by MSRecord::owned
+            let m = unsafe { &mut *self.0 };
+            if !m.record.is_null() {
+                let raw = ptr::slice_from_raw_parts_mut(m.record as *mut u8, m.reclen as usize);
+                drop(unsafe { Box::from_raw(raw) });
+                m.record = ptr::null();
+            }
+        }
+        unsafe { libmseed_sys::msr3_free(&mut self.0) };
+    }
+}
+
+impl Drop for MSFileParam {
+    fn drop(&mut self) {
+        // Errors cannot be reported from drop
+        let _ = self.close();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    // Midnight 2020-01-01, the start time of packed test data
+    pub(crate) fn t0() -> time::OffsetDateTime {
+        time::Date::try_from_ymd(2020, 1, 1)
+            .unwrap()
+            .midnight()
+            .assume_utc()
+    }
+    pub(crate) trait TestSample: Sized {
+        fn pack(pp: &MSPackParam, samples: &[Self]) -> Vec<u8>;
+    }
+    impl TestSample for i32 {
+        fn pack(pp: &MSPackParam, samples: &[Self]) -> Vec<u8> {
+            pp.pack_i32(samples).unwrap()
+        }
+    }
+    impl TestSample for f32 {
+        fn pack(pp: &MSPackParam, samples: &[Self]) -> Vec<u8> {
+            pp.pack_f32(samples).unwrap()
+        }
+    }
+    impl TestSample for f64 {
+        fn pack(pp: &MSPackParam, samples: &[Self]) -> Vec<u8> {
+            pp.pack_f64(samples).unwrap()
+        }
+    }
+    // Text samples
+    impl TestSample for u8 {
+        fn pack(pp: &MSPackParam, samples: &[Self]) -> Vec<u8> {
+            pp.pack_text(std::str::from_utf8(samples).unwrap()).unwrap()
+        }
+    }
+    // Samples packed as FDSN:XX_TEST__B_H_Z at `rate` Hz starting at t0()
+    pub(crate) fn packed<S: TestSample>(samples: &[S], rate: f64) -> Vec<u8> {
+        S::pack(
+            &MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0(), rate),
+            samples,
+        )
+    }
+    // Trace list read from packed(samples, rate), a single segment
+    pub(crate) fn segment_from<S: TestSample>(samples: &[S], rate: f64) -> MSTraceList {
+        let mut mstl = MSTraceList::from_buffer(packed(samples, rate));
+        mstl.read().unwrap();
+        mstl
+    }
+    pub(crate) fn first_segment(mstl: &MSTraceList) -> MSTraceSegment<'_> {
+        mstl.traces().next().unwrap().segments().next().unwrap()
+    }
+    // Two blocks of 100 samples at 10 Hz, the second starts 0.2 s
+    // (2 samples) later than expected
+    fn gapped_records() -> Vec<u8> {
+        let t1 = t0() + time::Duration::milliseconds(10_200);
+        let data: Vec<i32> = (0..100).collect();
+        let mut buf = packed(&data, 10.0);
+        buf.extend(
+            MSPackParam::new("FDSN:XX_TEST__B_H_Z", t1, 10.0)
+                .pack_i32(&data)
+                .unwrap(),
+        );
+        buf
+    }
     #[test]
     fn file_param() {
         let fp = MSFileParam::new("./tests/multiple.seed");
@@ -450,9 +2728,339 @@ mod tests {
         }
     }
     #[test]
+    fn file_param_decoded() {
+        for r in MSFileParam::new("./tests/multiple.seed") {
+            let rec = r.unwrap();
+            assert!(rec.is_fully_decoded());
+        }
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        fp.unpack_data(false);
+        for r in fp {
+            let rec = r.unwrap();
+            assert!(rec.samplecnt() > 0);
+            assert_eq!(rec.numsamples(), 0);
+            assert!(!rec.is_fully_decoded());
+        }
+    }
+    #[test]
+    fn sample_period() {
+        let t0 = t0();
+        // One sample every 100 seconds stored as a period
+        let data: Vec<i32> = (0..10).collect();
+        let buf = MSPackParam::new("FDSN:XX_TEST__V_H_Z", t0, -100.0)
+            .pack_i32(&data)
+            .unwrap();
+        let mut bp = MSBufferParam::new(buf.clone());
+        let rec = bp.read_record().unwrap();
+        assert!((rec.samprate() - 0.01).abs() < 1e-12);
+        assert!((rec.header().samprate - 0.01).abs() < 1e-12);
+
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        let seg = first_segment(&mstl);
+        assert!((seg.samprate() - 0.01).abs() < 1e-12);
+    }
+    #[test]
+    fn record_data_quality() {
+        let t0 = t0();
+        let data: Vec<i32> = (0..100).collect();
+        for q in &['R', 'D', 'Q', 'M'] {
+            for v2 in &[true, false] {
+                let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, 10.0);
+                pp.data_quality(*q).unwrap();
+                pp.format_version2(*v2);
+                let mut bp = MSBufferParam::new(pp.pack_i32(&data).unwrap());
+                let rec = bp.read_record().unwrap();
+                assert_eq!(rec.data_quality(), *q);
+                assert_eq!(Some(rec.pubversion()), quality_to_pubversion(*q));
+            }
+        }
+        let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, 10.0);
+        assert!(pp.data_quality('X').is_err());
+    }
+    #[test]
+    fn record_factor_mult() {
+        let t0 = t0();
+        let data: Vec<i32> = (0..10).collect();
+        for rate in &[20.0, 100.0, 0.01, 0.1] {
+            let buf = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, *rate)
+                .pack_i32(&data)
+                .unwrap();
+            let mut bp = MSBufferParam::new(buf);
+            let rec = bp.read_record().unwrap();
+            let (f, m) = rec.sample_rate_factor_mult().unwrap();
+            assert!((factor_mult_to_samprate(f, m) - rate).abs() < 1e-12);
+        }
+        assert_eq!(factor_mult_to_samprate(-100, 1), 0.01);
+        assert_eq!(factor_mult_to_samprate(20, 1), 20.0);
+        assert_eq!(factor_mult_to_samprate(1, -10), 0.1);
+        assert_eq!(factor_mult_to_samprate(-10, -10), 0.01);
+        assert_eq!(factor_mult_to_samprate(0, 1), 0.0);
+    }
+    #[test]
+    fn record_debug_dump() {
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let rec = fp.read_record().unwrap();
+        let dump = rec.debug_dump();
+        assert!(dump.starts_with("FDSN:IU_ANMO_00_B_H_Z, version 2, 512 bytes\n"));
+        assert!(dump.lines().nth(1).unwrap().starts_with("header, "));
+        let first = dump.lines().nth(2).unwrap();
+        assert!(first.starts_with("  0000  "));
+        assert!(dump.contains("  encoding: Steim2 (11)\n"));
+        assert!(dump.contains("  sample count: "));
+        // Raw bytes are kept with the record after the file is closed
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let rec = fp.read_record().unwrap();
+        fp.read_record().unwrap();
+        drop(fp);
+        assert_eq!(rec.debug_dump(), dump);
+
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let rec = MSBufferParam::new(buf).read_record().unwrap();
+        assert_eq!(rec.debug_dump(), dump);
+
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.record_list(true);
+        mstl.read().unwrap();
+        let seg = first_segment(&mstl);
+        let rec = seg.records().next().unwrap();
+        let dump = rec.record().debug_dump();
+        assert_eq!(dump.lines().nth(1), Some("raw record not available"));
+    }
+    #[test]
+    fn time_strings() {
+        let t0 = t0();
+        let nst = time_to_nstime(t0 + time::Duration::nanoseconds(123_456_789));
+        assert_eq!(nstime_to_string(nst), "2020,001,00:00:00.123456");
+        let iso = nstime_format(
+            nst,
+            libmseed_sys::ms_timeformat_t_ISOMONTHDAY,
+            libmseed_sys::ms_subseconds_t_NANO,
+        );
+        assert_eq!(iso, "2020-01-01T00:00:00.123456789");
+        // Extreme times do not panic
+        nstime_to_string(i64::MAX);
+        nstime_to_string(i64::MIN);
+    }
+    #[test]
+    fn record_timing_quality() {
+        let start = t0();
+        let data: Vec<i32> = (0..100).collect();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", start, 20.0);
+        pp.format_version2(true);
+        pp.extra_headers(Some(r#"{"FDSN":{"Time":{"Quality":80}}}"#))
+            .unwrap();
+        // Timing quality is packed into, and read from, a blockette 1001
+        let mut bp = MSBufferParam::new(pp.pack_i32(&data).unwrap());
+        let rec = bp.read_record().unwrap();
+        assert_eq!(rec.timing_quality(), Some(80));
+        assert_eq!(rec.samprate(), 20.0);
+
+        let buf = MSPackParam::new("FDSN:XX_TEST__B_H_Z", start, 20.0)
+            .pack_i32(&data)
+            .unwrap();
+        let mut bp = MSBufferParam::new(buf);
+        let rec = bp.read_record().unwrap();
+        assert_eq!(rec.timing_quality(), None);
+        assert_eq!(rec.time_correction(), None);
+    }
+    #[test]
+    fn record_content_hash() {
+        let t0 = t0();
+        let sid = "FDSN:XX_TEST__B_H_Z";
+        let data: Vec<i32> = (0..100).collect();
+        let hash = |buf: Vec<u8>| {
+            let mut bp = MSBufferParam::new(buf);
+            bp.read_record().unwrap().content_hash()
+        };
+        let mut pp = MSPackParam::new(sid, t0, 10.0);
+        let h0 = hash(pp.pack_i32(&data).unwrap());
+        pp.encoding(MSEncoding::Integer32);
+        pp.record_length(512);
+        assert_eq!(h0, hash(pp.pack_i32(&data).unwrap()));
+        let fdata: Vec<f64> = data.iter().map(|x| *x as f64).collect();
+        assert_eq!(
+            h0,
+            hash(MSPackParam::new(sid, t0, 10.0).pack_f64(&fdata).unwrap())
+        );
+
+        let mut other = data.clone();
+        other[50] = 0;
+        assert_ne!(
+            h0,
+            hash(MSPackParam::new(sid, t0, 10.0).pack_i32(&other).unwrap())
+        );
+        let t1 = t0 + time::Duration::seconds(1);
+        assert_ne!(
+            h0,
+            hash(MSPackParam::new(sid, t1, 10.0).pack_i32(&data).unwrap())
+        );
+    }
+    #[test]
+    fn record_epoch_nanos() {
+        let t0 = t0();
+        let buf = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, 1.0)
+            .pack_i32(&[1, 2, 3])
+            .unwrap();
+        let mut bp = MSBufferParam::new(buf);
+        let rec = bp.read_record().unwrap();
+        assert_eq!(rec.start_epoch_nanos(), 1_577_836_800_000_000_000);
+        let nst = epoch_nanos_to_nstime(rec.start_epoch_nanos());
+        assert_eq!(nstime_to_time(nst), rec.start_time());
+    }
+    #[test]
+    fn file_param_headers() {
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let mut headers = vec![];
+        loop {
+            match fp.read_header() {
+                Ok(h) => headers.push(h),
+                Err(MSError::EOF) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+        }
+        let recs: Vec<RecordHeader> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        assert_eq!(headers, recs);
+        assert_eq!(headers.iter().map(|h| h.samplecnt).sum::<i64>(), 288000);
+        assert!(headers.iter().all(|h| h.sid == "FDSN:IU_ANMO_00_B_H_Z"));
+    }
+    #[test]
+    fn empty_files() {
+        for (name, len) in &[("libmseed_empty.mseed", 0), ("libmseed_short.mseed", 10)] {
+            let file = std::env::temp_dir().join(name);
+            std::fs::write(&file, vec![b'0'; *len]).unwrap();
+            assert_eq!(MSFileParam::new(&file).count(), 0);
+            let mut mstl = MSTraceList::new(&file);
+            mstl.read().unwrap();
+            assert_eq!(mstl.numtraces(), 0);
+            assert_eq!(mstl.traces().count(), 0);
+            std::fs::remove_file(&file).unwrap();
+
+            let mut mstl = MSTraceList::from_buffer(vec![b'0'; *len]);
+            mstl.read().unwrap();
+            assert_eq!(mstl.numtraces(), 0);
+            assert_eq!(MSBufferParam::new(vec![b'0'; *len]).count(), 0);
+        }
+    }
+    #[test]
+    fn read_errors() {
+        let mut mstl = MSTraceList::new("./tests/does_not_exist.seed");
+        assert!(matches!(mstl.read(), Err(MSError::Error(_))));
+        let mut fp = MSFileParam::new("./tests/does_not_exist.seed");
+        assert!(matches!(fp.next(), Some(Err(MSError::Error(_)))));
+        let mut fp = MSFileParam::new("./tests/bad\0path.seed");
+        assert!(matches!(fp.next(), Some(Err(MSError::Error(_)))));
+    }
+    #[test]
+    fn record_implied_rate() {
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let r0 = fp.read_record().unwrap();
+        let r1 = fp.read_record().unwrap();
+        assert!((r0.implied_rate(&r1) - r0.samprate()).abs() < 1e-3);
+        assert!(r1.implied_rate(&r0).is_nan());
+    }
+    #[test]
+    fn file_param_rewind() {
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let first: Vec<_> = (&mut fp).map(|r| r.unwrap().header()).collect();
+        assert!(fp.read_record().is_err());
+        fp.rewind().unwrap();
+        assert_eq!(fp.offset(), 0);
+        let second: Vec<_> = (&mut fp).map(|r| r.unwrap().header()).collect();
+        assert_eq!(first, second);
+
+        // Part way through with a fixed record length
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        fp.record_length(Some(512));
+        let a = fp.read_header().unwrap();
+        fp.read_header().unwrap();
+        fp.rewind().unwrap();
+        assert_eq!(fp.read_header().unwrap(), a);
+        assert_eq!(fp.offset(), 0);
+    }
+    #[test]
+    fn file_param_lazy() {
+        let headers: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let mut lazy = vec![];
+        while let Ok(rec) = fp.read_lazy() {
+            lazy.push(rec);
+        }
+        assert_eq!(lazy.len(), headers.len());
+        // Records are unpacked after later records have been read
+        for (n, rec) in lazy.iter_mut().enumerate() {
+            assert_eq!(rec.header(), &headers[n]);
+            if n % 100 == 0 {
+                let r = rec.unpack().unwrap();
+                assert!(r.is_fully_decoded());
+                assert_eq!(r.numsamples(), headers[n].samplecnt);
+            }
+        }
+    }
+    #[test]
+    fn file_param_record_length() {
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        fp.record_length(Some(512));
+        let mut headers = vec![];
+        let mut offsets = vec![];
+        loop {
+            match fp.read_record() {
+                Ok(r) => headers.push(r.header()),
+                Err(MSError::EOF) => break,
+                Err(e) => panic!("{:?}", e),
+            }
+            offsets.push(fp.offset());
+        }
+        let all: Vec<_> = MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header())
+            .collect();
+        assert_eq!(headers, all);
+        assert_eq!(offsets[1], 512);
+
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        fp.record_length(Some(4096));
+        assert!(matches!(fp.next(), Some(Err(MSError::Error(_)))));
+    }
+    #[test]
+    fn file_param_record_length_no_b1000() {
+        let t0 = t0();
+        let data: Vec<i32> = (0..300).collect();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, 10.0);
+        pp.format_version2(true);
+        pp.record_length(512);
+        pp.encoding(MSEncoding::Integer32);
+        let mut buf = pp.pack_i32(&data).unwrap();
+        // Remove the blockette 1000, the only blockette, from each record
+        for rec in buf.chunks_mut(512) {
+            assert_eq!(rec[39], 1);
+            assert_eq!(u16::from_be_bytes([rec[48], rec[49]]), 1000);
+            rec[39] = 0;
+            for b in rec[46..56].iter_mut() {
+                *b = 0;
+            }
+        }
+        let file = std::env::temp_dir().join("libmseed_no_b1000.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        let mut fp = MSFileParam::new(&file);
+        fp.record_length(Some(512));
+        fp.unpack_data(false);
+        let headers: Vec<_> = (&mut fp).map(|r| r.map(|r| r.header())).collect();
+        drop(fp);
+        std::fs::remove_file(&file).unwrap();
+        let headers: Vec<_> = headers.into_iter().map(|h| h.unwrap()).collect();
+        assert_eq!(headers.len(), buf.len() / 512);
+        assert!(headers.iter().all(|h| h.reclen == 512));
+        assert_eq!(headers.iter().map(|h| h.samplecnt).sum::<i64>(), 300);
+        assert_eq!(headers[0].start_nstime, time_to_nstime(t0));
+    }
+    #[test]
     fn trace_list() {
         let mut fp = MSTraceList::new("./tests/multiple.seed");
-        fp.read();
+        fp.read().unwrap();
         assert_eq!(fp.numtraces(), 1);
         for trace in fp.traces() {
             for segment in trace.segments() {
@@ -461,4 +3069,670 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn trace_list_no_unpack() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.unpack_data(false);
+        fp.read().unwrap();
+        assert_eq!(fp.numtraces(), 1);
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                assert_eq!(segment.samplecnt(), 288000);
+                assert!(!segment.data_unpacked());
+                assert!(segment.to_vec_i32().is_empty());
+            }
+        }
+    }
+    #[test]
+    fn merge_within_tolerance() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        assert_eq!(mstl.traces().next().unwrap().numsegments(), 2);
+        mstl.merge(0.1);
+        assert_eq!(mstl.traces().next().unwrap().numsegments(), 2);
+        mstl.merge(0.25);
+        let trace = mstl.traces().next().unwrap();
+        assert_eq!(trace.numsegments(), 1);
+        let seg = trace.segments().next().unwrap();
+        assert_eq!(seg.samplecnt(), 200);
+        let data: Vec<i32> = (0..100).collect();
+        assert_eq!(seg.to_vec_i32()[100..], data[..]);
+    }
+    #[test]
+    fn trace_write_csv() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let mut out = vec![];
+        trace.write_csv_to(&mut out, CsvTime::Epoch).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 201);
+        assert_eq!(lines[0], "time,value");
+        assert_eq!(lines[1], "1577836800.000000000,0");
+        assert_eq!(lines[2], "1577836800.100000000,1");
+        assert_eq!(lines[101], "1577836810.200000000,0");
+
+        let file = std::env::temp_dir().join("libmseed_write_csv.csv");
+        trace.write_csv(&file, CsvTime::Iso).unwrap();
+        let out = std::fs::read_to_string(&file).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[1], "2020-01-01T00:00:00.000000000,0");
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn trace_list_data_diff() {
+        let mut a = MSTraceList::new("./tests/multiple.seed");
+        a.read().unwrap();
+        let trace = a.traces().next().unwrap();
+        let seg = trace.segments().next().unwrap();
+        let mut data = seg.to_vec_i32();
+        let mut pp = MSPackParam::new(&trace.sid(), trace.start_time(), seg.samprate());
+        pp.encoding(MSEncoding::Integer32);
+        let mut b = MSTraceList::from_buffer(pp.pack_i32(&data).unwrap());
+        b.read().unwrap();
+        assert!(a.data_eq(&b));
+
+        data[10] += 1;
+        data[20] += 1;
+        let mut c = MSTraceList::from_buffer(pp.pack_i32(&data).unwrap());
+        c.read().unwrap();
+        assert_eq!(
+            a.data_diff(&c),
+            vec![DataDiff::Samples {
+                sid: trace.sid(),
+                first: 10,
+                count: 2
+            }]
+        );
+        let mut d = MSTraceList::from_buffer(gapped_records());
+        d.read().unwrap();
+        assert_eq!(
+            a.data_diff(&d),
+            vec![
+                DataDiff::OnlyLeft(trace.sid()),
+                DataDiff::OnlyRight("FDSN:XX_TEST__B_H_Z".into())
+            ]
+        );
+
+        // Publication versions split into separate traces of one source id
+        let mut buf = vec![];
+        for version in 1..=2 {
+            pp.pubversion(version);
+            buf.extend(pp.pack_i32(&data).unwrap());
+        }
+        let mut e = MSTraceList::from_buffer(buf);
+        e.split_version(true);
+        e.read().unwrap();
+        assert_eq!(a.data_diff(&e), vec![DataDiff::Duplicate(trace.sid())]);
+        assert_eq!(e.data_diff(&a), vec![DataDiff::Duplicate(trace.sid())]);
+    }
+    #[test]
+    fn trace_list_record_counts() {
+        let n = MSFileParam::new("./tests/multiple.seed").count() as u64;
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.read().unwrap();
+        assert_eq!(mstl.records_read(), None);
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.count_records(true);
+        mstl.read().unwrap();
+        assert_eq!(mstl.records_read(), Some(n));
+        assert_eq!(first_segment(&mstl).samplecnt(), 288000);
+        assert_eq!(mstl.records_skipped(), Some(0));
+
+        let buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.count_records(true);
+        mstl.read().unwrap();
+        assert_eq!(mstl.records_read(), Some(n));
+
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.count_records(true);
+        mstl.read().unwrap();
+        assert_eq!(mstl.records_read(), Some(2));
+
+        // Read errors are reported, not counted
+        let mut buf = std::fs::read("./tests/multiple.seed").unwrap();
+        buf.truncate(700);
+        let file = std::env::temp_dir().join("libmseed_count_truncated.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        let mut mstl = MSTraceList::new(&file);
+        mstl.count_records(true);
+        let counted = mstl.read();
+        std::fs::remove_file(&file).unwrap();
+        assert!(counted.is_err());
+    }
+    #[test]
+    fn trace_list_overlaps() {
+        let t0 = t0();
+        let data: Vec<i32> = (0..100).collect();
+        let sid = "FDSN:XX_TEST__B_H_Z";
+        let t1 = t0 + time::Duration::seconds(5);
+        let mut buf = packed(&data, 10.0);
+        buf.extend(MSPackParam::new(sid, t1, 10.0).pack_i32(&data).unwrap());
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        assert_eq!(
+            mstl.overlaps(),
+            vec![Overlap {
+                sid: sid.into(),
+                start: t1,
+                end: t0 + time::Duration::milliseconds(9_900),
+                first: 0,
+                second: 1,
+            }]
+        );
+
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        assert!(mstl.overlaps().is_empty());
+    }
+    #[test]
+    fn trace_list_unread() {
+        let mstl = MSTraceList::new("./tests/multiple.seed");
+        assert_eq!(mstl.numtraces(), 0);
+        assert_eq!(mstl.traces().count(), 0);
+        assert!(mstl.channels().is_empty());
+    }
+    #[test]
+    fn source_id_conversion() {
+        let id = SourceId::from_sid("FDSN:IU_ANMO_00_B_H_Z").unwrap();
+        assert_eq!(id.to_string(), "IU.ANMO.00.BHZ");
+        assert_eq!(id.to_sid().unwrap(), "FDSN:IU_ANMO_00_B_H_Z");
+        let id = SourceId::from_sid("FDSN:XX_TEST__L_HH_Z").unwrap();
+        assert_eq!(id.channel, "L_HH_Z");
+        assert_eq!(id.location, "");
+        assert_eq!(id.to_sid().unwrap(), "FDSN:XX_TEST__L_HH_Z");
+        assert!(SourceId::from_sid("not a source id").is_err());
+
+        assert_eq!(channel_to_seed2("B_H_Z").as_deref(), Some("BHZ"));
+        assert_eq!(channel_to_seed2("L_HH_Z"), None);
+        assert_eq!(channel_to_seed2("BHZ"), None);
+        assert_eq!(channel_from_seed2("BHZ").as_deref(), Some("B_H_Z"));
+        assert_eq!(channel_from_seed2("B_H_Z"), None);
+        assert_eq!(channel_from_seed2("LH"), None);
+    }
+    #[test]
+    fn trace_list_open() {
+        let mstl = MSTraceList::open("./tests/multiple.seed").unwrap();
+        assert_eq!(mstl.numtraces(), 1);
+        assert!(MSTraceList::open("./tests/does-not-exist.seed").is_err());
+    }
+    #[test]
+    fn trace_list_channels() {
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.unpack_data(false);
+        mstl.read().unwrap();
+        let channels = mstl.channels();
+        assert_eq!(
+            channels,
+            vec![SourceId {
+                network: "IU".into(),
+                station: "ANMO".into(),
+                location: "00".into(),
+                channel: "BHZ".into(),
+            }]
+        );
+        assert_eq!(channels[0].to_string(), "IU.ANMO.00.BHZ");
+    }
+    #[test]
+    fn trace_to_vec_opt() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let v = trace.to_vec_opt_f64();
+        // 10 Hz with the second block 0.2 s late, samples at 10.0 and 10.1 s missing
+        assert_eq!(v.len(), 202);
+        assert_eq!(v[0], Some(0.0));
+        assert_eq!(v[99], Some(99.0));
+        assert_eq!(v[100], None);
+        assert_eq!(v[101], None);
+        assert_eq!(v[102], Some(0.0));
+        assert_eq!(v.iter().filter(|x| x.is_none()).count(), 2);
+    }
+    #[test]
+    fn segment_offsets() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let offsets: Vec<_> = trace
+            .segments_with_offsets()
+            .iter()
+            .map(|(i, seg)| (*i, seg.samplecnt()))
+            .collect();
+        assert_eq!(offsets, vec![(0, 100), (100, 100)]);
+    }
+    #[test]
+    fn trace_list_all_segments() {
+        let t0 = t0();
+        let data: Vec<i32> = (0..100).collect();
+        let mut buf = gapped_records();
+        buf.extend(
+            MSPackParam::new("FDSN:XX_TEST__B_H_N", t0, 10.0)
+                .pack_i32(&data)
+                .unwrap(),
+        );
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        let segs: Vec<_> = mstl
+            .all_segments()
+            .map(|(id, seg)| (id.channel(), seg.samplecnt()))
+            .collect();
+        assert_eq!(segs.len(), 3);
+        assert_eq!(segs.iter().filter(|(cha, _)| cha == "BHZ").count(), 2);
+        assert!(segs.contains(&("BHN".to_string(), 100)));
+        assert_eq!(
+            MSTraceList::new("./tests/multiple.seed")
+                .all_segments()
+                .count(),
+            0
+        );
+    }
+    #[test]
+    fn segment_arrays() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        assert_eq!(trace.segment_arrays_i32()[0][99], 99);
+        assert_eq!(trace.segment_arrays_f32()[1].len(), 100);
+        let arrays = trace.segment_arrays_f64();
+        let starts = trace.segment_start_times();
+        assert_eq!(arrays.len(), 2);
+        assert_eq!(starts.len(), 2);
+        assert_eq!(arrays[1][..3], [0.0, 1.0, 2.0]);
+        for (seg, (v, t)) in trace.segments().zip(arrays.iter().zip(&starts)) {
+            assert_eq!(v.len(), seg.samplecnt() as usize);
+            assert_eq!(*t, seg.start_time());
+        }
+    }
+    #[test]
+    fn read_channel() {
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let (times, values) = read_channel_f64("./tests/multiple.seed", "IU_ANMO_00_BHZ").unwrap();
+        assert_eq!(values.len(), 288000);
+        assert_eq!(times.len(), values.len());
+        assert_eq!(times[0], trace.start_time());
+        assert_eq!(times[times.len() - 1], trace.end_time());
+        let seg = trace.segments().next().unwrap();
+        assert_eq!(values[..10], seg.samples_f64()[..10]);
+        let (_, v) = read_channel_f64("./tests/multiple.seed", "FDSN:IU_ANMO_00_B_H_Z").unwrap();
+        assert_eq!(v, values);
+        assert!(read_channel_f64("./tests/multiple.seed", "IU_ANMO_00_BHN").is_err());
+    }
+    #[test]
+    fn segment_sample_size() {
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.read().unwrap();
+        let seg = first_segment(&mstl);
+        let size = seg.sampletype().unwrap().sample_size();
+        assert_eq!(size, 4);
+        assert_eq!(seg.datasize(), seg.numsamples() as u64 * size as u64);
+        assert_eq!(MSSampleType::Float64.sample_size(), 8);
+        assert_eq!(MSSampleType::Text.sample_size(), 1);
+    }
+    #[test]
+    fn trace_list_from_file() {
+        let file = File::open("./tests/multiple.seed").unwrap();
+        let mut fp = MSTraceList::from_file(file).unwrap();
+        fp.read().unwrap();
+        assert_eq!(fp.numtraces(), 1);
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                assert_eq!(segment.to_vec_i32().len(), 288000);
+            }
+        }
+    }
+    #[test]
+    fn debug_output() {
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        assert!(format!("{:?}", mstl).contains("traces: unread"));
+        mstl.read().unwrap();
+        assert!(format!("{:?}", mstl).contains("numtraces: 1"));
+        let trace = mstl.traces().next().unwrap();
+        let s = format!("{:?}", trace);
+        assert!(s.contains("FDSN:IU_ANMO_00_B_H_Z"));
+        let s = format!("{:?}", trace.segments().next().unwrap());
+        assert!(s.contains("samplecnt: 288000"));
+        assert!(s.contains("sampletype: 'i'"));
+        let mut fp = MSFileParam::new("./tests/multiple.seed");
+        let s = format!("{:?}", fp.next().unwrap().unwrap());
+        assert!(s.starts_with("MSRecord { sid: \"FDSN:IU_ANMO_00_B_H_Z\""));
+        assert!(!s.contains("0x"));
+        assert_eq!(format!("{:?}", MSRecord(ptr::null_mut())), "MSRecord(null)");
+        let s = format!("{:?}", fp);
+        assert!(s.contains("path: \"./tests/multiple.seed\""));
+        assert!(s.contains("open: true"));
+        assert!(!s.contains("0x"));
+        let s = format!("{:?}", mstl.traces());
+        assert!(s.starts_with("MSTraceIDIterator { next: MSTraceID { sid:"));
+        let mut segments = trace.segments();
+        segments.next();
+        assert_eq!(
+            format!("{:?}", segments),
+            "MSTraceSegmentIterator { next: MSTraceSegment(null) }"
+        );
+    }
+    #[test]
+    fn trace_list_display() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        for trace in fp.traces() {
+            let s = format!("{}", trace);
+            assert!(s.starts_with("FDSN:IU_ANMO_00_B_H_Z 2010,058,06:30:00"));
+            assert!(s.ends_with("20 Hz, 1 segments"));
+            for segment in trace.segments() {
+                let s = format!("{}", segment);
+                assert!(s.ends_with("20 Hz, 288000 samples, type i, unpacked"));
+            }
+        }
+    }
+    #[test]
+    fn samples_exceed_datasize() {
+        let mut data = [1i32, 2];
+        let mut seg: MS3TraceSeg = unsafe { std::mem::zeroed() };
+        seg.samplecnt = 10;
+        seg.numsamples = 10;
+        seg.sampletype = 'i' as _;
+        seg.datasamples = data.as_mut_ptr() as *mut _;
+        seg.datasize = std::mem::size_of_val(&data) as _;
+        let segment = MSTraceSegment(&mut seg, ptr::null_mut(), PhantomData);
+        assert!(segment.data_unpacked());
+        assert!(segment.to_vec_i32().is_empty());
+        assert!(segment.samples_i32().unwrap().is_empty());
+        // Not part of a trace
+        assert!(segment.repack(MSEncoding::Integer32, 512).is_err());
+        assert!(segment.samples_f64().is_empty());
+    }
+    #[test]
+    fn convert_segment() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        let i = fp
+            .traces()
+            .next()
+            .unwrap()
+            .segments()
+            .next()
+            .unwrap()
+            .to_vec_i32();
+        let f = fp
+            .traces()
+            .next()
+            .unwrap()
+            .segments()
+            .next()
+            .unwrap()
+            .to_vec_f64();
+        let segment = fp.traces().next().unwrap().segments().next().unwrap();
+        // Reading other types does not change the stored samples
+        assert_eq!(segment.sampletype().unwrap(), MSSampleType::Integer32);
+        assert_eq!(i[10] as f64, f[10]);
+        fp.convert(MSSampleType::Float64).unwrap();
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                assert_eq!(segment.sampletype().unwrap(), MSSampleType::Float64);
+                assert!(matches!(segment.samples_f64(), Cow::Borrowed(_)));
+                assert_eq!(segment.to_vec_f64(), f);
+            }
+        }
+    }
+    #[test]
+    fn trace_list_records() {
+        let n = MSFileParam::new("./tests/multiple.seed").count();
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.record_list(true);
+        fp.read().unwrap();
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                let recs: Vec<_> = segment.records().collect();
+                assert_eq!(recs.len(), n);
+                assert_eq!(recs[0].offset(), 0);
+                assert!(recs.windows(2).all(|w| w[0].offset() < w[1].offset()));
+                assert_eq!(recs[0].start_time(), segment.start_time());
+            }
+        }
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                assert_eq!(segment.records().count(), 0);
+            }
+        }
+    }
+    #[test]
+    fn text_segment() {
+        let msg = "Station log: sensor replaced";
+        let mut mstl = segment_from(msg.as_bytes(), 0.0);
+        let seg = first_segment(&mstl);
+        assert_eq!(seg.sampletype().unwrap(), MSSampleType::Text);
+        assert!(seg.data_unpacked());
+        assert_eq!(seg.samplecnt() as usize, msg.len());
+        assert_eq!(seg.text().as_deref(), Some(msg));
+        assert!(seg.convert(MSSampleType::Float64).is_err());
+        assert!(seg.to_vec_f64().is_empty());
+        assert!(seg.samples_i32().unwrap().is_empty());
+        // Text segments are left unchanged
+        mstl.convert(MSSampleType::Float64).unwrap();
+    }
+    #[test]
+    fn repack_segment() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        let trace = fp.traces().next().unwrap();
+        let seg = trace.segments().next().unwrap();
+        let buf = seg.repack(MSEncoding::Steim1, 512).unwrap();
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        let trace2 = mstl.traces().next().unwrap();
+        assert_eq!(trace2.sid(), trace.sid());
+        let seg2 = trace2.segments().next().unwrap();
+        assert_eq!(seg2.start_time(), seg.start_time());
+        assert_eq!(seg2.to_vec_i32(), seg.to_vec_i32());
+
+        let data: Vec<f64> = (0..100).map(|x| x as f64 + 0.5).collect();
+        let mstl = segment_from(&data, 10.0);
+        let seg = first_segment(&mstl);
+        assert!(seg.repack(MSEncoding::Steim2, 512).is_err());
+        assert!(seg.repack(MSEncoding::Float64, 512).is_ok());
+    }
+    #[test]
+    fn segment_next_sample_time() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let segs: Vec<_> = trace.segments().collect();
+        let t0 = segs[0].start_time();
+        assert_eq!(segs[0].next_sample_time(), t0 + time::Duration::seconds(10));
+        assert_eq!(
+            segs[1].start_time() - segs[0].next_sample_time(),
+            time::Duration::milliseconds(200)
+        );
+    }
+    #[test]
+    fn segment_windows() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let seg = first_segment(&mstl);
+        // 100 samples at 10 Hz, 3 s windows with 1 s overlap
+        let w: Vec<_> = seg.windows_f64(3.0, 1.0).collect();
+        let starts: Vec<_> = w.iter().map(|(t, _)| *t - seg.start_time()).collect();
+        let lens: Vec<_> = w.iter().map(|(_, v)| v.len()).collect();
+        assert_eq!(starts.len(), 5);
+        assert_eq!(starts[1], time::Duration::seconds(2));
+        assert_eq!(starts[4], time::Duration::seconds(8));
+        assert_eq!(lens, vec![30, 30, 30, 30, 20]);
+        assert_eq!(w[1].1[0], 20.0);
+        assert_eq!(seg.windows_f64(3.0, 3.0).count(), 0);
+        assert_eq!(seg.windows_f64(0.01, 0.0).count(), 0);
+        assert_eq!(seg.windows_f64(20.0, 0.0).count(), 1);
+    }
+    #[test]
+    fn segment_flatlined() {
+        let flatlined = |data: &[i32], threshold, constant| {
+            first_segment(&segment_from(data, 10.0)).is_flatlined(threshold, constant)
+        };
+        assert!(flatlined(&[0; 100], 0.0, false));
+        assert!(flatlined(&[7; 100], 0.0, true));
+        assert!(!flatlined(&[7; 100], 0.0, false));
+        let data: Vec<i32> = (0..100).map(|x| 7 + x % 2).collect();
+        assert!(!flatlined(&data, 0.0, true));
+        assert!(flatlined(&data, 1.0, true));
+        let data: Vec<i32> = (0..100).collect();
+        assert!(!flatlined(&data, 10.0, true));
+    }
+    #[test]
+    fn segment_envelope() {
+        let mut mstl = MSTraceList::from_buffer(gapped_records());
+        mstl.read().unwrap();
+        let seg = first_segment(&mstl);
+        // Samples 0..100
+        let e = seg.envelope_f64(4);
+        assert_eq!(
+            e,
+            vec![(0.0, 24.0), (25.0, 49.0), (50.0, 74.0), (75.0, 99.0)]
+        );
+        let e = seg.envelope_f64(3);
+        assert_eq!(e.len(), 3);
+        assert_eq!(e[0].0, 0.0);
+        assert_eq!(e[2].1, 99.0);
+        let e = seg.envelope_f64(1000);
+        assert_eq!(e.len(), 100);
+        assert_eq!(e[10], (10.0, 10.0));
+        assert!(seg.envelope_f64(0).is_empty());
+    }
+    #[test]
+    fn segment_to_vec_i16() {
+        let data: Vec<i32> = (0..100).map(|x| x * 300 - 15000).collect();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0(), 10.0);
+        pp.encoding(MSEncoding::Integer16);
+        let mut mstl = MSTraceList::from_buffer(pp.pack_i32(&data).unwrap());
+        mstl.read().unwrap();
+        let want: Vec<i16> = data.iter().map(|x| *x as i16).collect();
+        assert_eq!(first_segment(&mstl).to_vec_i16(), Some(want));
+
+        // Out of the range of an i16
+        let data = [0i32, 1, 40000, 2];
+        let mstl = segment_from(&data, 10.0);
+        assert_eq!(first_segment(&mstl).to_vec_i16(), None);
+
+        let mut mstl = MSTraceList::from_buffer(packed(&data, 10.0));
+        mstl.unpack_data(false);
+        mstl.read().unwrap();
+        assert_eq!(first_segment(&mstl).to_vec_i16(), Some(vec![]));
+
+        let mstl = segment_from(b"not numeric", 0.0);
+        assert_eq!(first_segment(&mstl).to_vec_i16(), None);
+    }
+    #[test]
+    fn segment_spikes() {
+        let mut data: Vec<i32> = (0..200).map(|x| x % 5).collect();
+        data[50] = 1000;
+        data[120] = -800;
+        let mstl = segment_from(&data, 10.0);
+        let seg = first_segment(&mstl);
+        assert_eq!(
+            seg.detect_spikes(5.0, 20),
+            vec![(50, 1000.0), (120, -800.0)]
+        );
+        assert!(seg.detect_spikes(5.0, 1).is_empty());
+
+        let mstl = segment_from(&[0.1f64; 100], 10.0);
+        assert!(first_segment(&mstl).detect_spikes(3.0, 10).is_empty());
+    }
+    #[test]
+    fn segment_rms_peak() {
+        let data: Vec<i32> = (0..100).map(|x| if x % 2 == 0 { 3 } else { -4 }).collect();
+        let mstl = segment_from(&data, 10.0);
+        let seg = first_segment(&mstl);
+        assert!((seg.rms() - 12.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(seg.peak_amplitude(), 4.0);
+        assert_eq!(seg.count_clipped(-4, 4), 50);
+        assert_eq!(seg.count_clipped(-3, 3), 100);
+        assert_eq!(seg.count_clipped(-5, 5), 0);
+
+        let mstl = segment_from(b"not numeric", 0.0);
+        let seg = first_segment(&mstl);
+        assert!(seg.rms().is_nan());
+        assert!(seg.peak_amplitude().is_nan());
+        assert_eq!(seg.count_clipped(0, 0), 0);
+    }
+    #[test]
+    fn segment_copy_into() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        let trace = fp.traces().next().unwrap();
+        let seg = trace.segments().next().unwrap();
+        let mut buf = vec![0.0; 300000];
+        let n = seg.copy_into_f64(&mut buf).unwrap();
+        assert_eq!(n, 288000);
+        assert_eq!(buf[..n], seg.to_vec_f64()[..]);
+        let mut small = vec![0.0; 10];
+        assert!(seg.copy_into_f64(&mut small).is_err());
+    }
+    #[test]
+    fn trace_list_encodings() {
+        let n = MSFileParam::new("./tests/multiple.seed").count() as u64;
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.record_list(true);
+        fp.read().unwrap();
+        let enc = fp.encodings().unwrap();
+        assert_eq!(enc.len(), 1);
+        assert_eq!(enc.get(&MSEncoding::Steim2), Some(&n));
+        assert_eq!(MSEncoding::from_code(11), MSEncoding::Steim2);
+        assert_eq!(MSEncoding::from_code(30), MSEncoding::Other(30));
+        assert_eq!(MSEncoding::Steim2.as_i8().unwrap(), 11);
+        assert_eq!(MSEncoding::Other(30).as_i8().unwrap(), 30);
+        assert!(MSEncoding::Other(200).as_i8().is_err());
+
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        assert!(fp.encodings().is_err());
+    }
+    #[test]
+    fn segment_integer_valued() {
+        for (data, expected) in &[
+            ((0..100).map(|x| x as f64).collect::<Vec<_>>(), true),
+            ((0..100).map(|x| x as f64 + 0.5).collect::<Vec<_>>(), false),
+            // Within epsilon of a whole number
+            (
+                (0..100)
+                    .map(|x| (x as f64 * 0.1) * 10.0)
+                    .collect::<Vec<_>>(),
+                true,
+            ),
+        ] {
+            let mstl = segment_from(data, 10.0);
+            let seg = first_segment(&mstl);
+            assert_eq!(seg.is_integer_valued(), *expected);
+            assert_eq!(seg.samples_i32().is_some(), *expected);
+            let repacked = seg.repack(MSEncoding::Steim2, 512);
+            assert_eq!(repacked.is_ok(), *expected);
+            if let Ok(buf) = repacked {
+                let mut mstl = MSTraceList::from_buffer(buf);
+                mstl.read().unwrap();
+                assert_eq!(
+                    first_segment(&mstl).to_vec_i32(),
+                    (0..100).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+    #[test]
+    fn samples_cow() {
+        let mut fp = MSTraceList::new("./tests/multiple.seed");
+        fp.read().unwrap();
+        for trace in fp.traces() {
+            for segment in trace.segments() {
+                let i = segment.samples_i32().unwrap();
+                assert!(matches!(i, Cow::Borrowed(_)));
+                let f = segment.samples_f64();
+                assert!(matches!(f, Cow::Owned(_)));
+                assert_eq!(i.len(), 288000);
+                assert_eq!(f.len(), 288000);
+                assert_eq!(i[0] as f64, f[0]);
+            }
+        }
+    }
 }
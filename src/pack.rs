@@ -0,0 +1,352 @@
+use libmseed_sys::MS3Record;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+use std::slice::from_raw_parts;
+
+use crate::{
+    check_count, quality_to_pubversion, string_to_i8, time_to_nstime, MSEncoding, MSError,
+};
+
+/// Byte order of packed records
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum ByteOrder {
+    Big,
+    Little,
+}
+
+/// Parameters for packing data into miniSEED records
+///
+/// Caller controlled header fields are the source id, start time of the first
+/// record, sample rate, record length, encoding, publication version, record
+/// flags, extra headers and the miniSEED2 sequence number.  The number of
+/// samples, start time of each following record, data offsets and CRC are
+/// computed when packing.
+#[derive(Debug)]
+pub struct MSPackParam {
+    sid: String,
+    pub(crate) starttime: i64,
+    pub(crate) samprate: f64,
+    encoding: Option<MSEncoding>,
+    pub(crate) reclen: i32,
+    pubversion: u8,
+    extra: Option<CString>,
+    flags: u32,
+    byteorder: Option<ByteOrder>,
+    recflags: u8,
+    pub(crate) seqnum: Option<u32>,
+    verbose: i8,
+}
+
+unsafe extern "C" fn record_handler(record: *mut c_char, reclen: c_int, handlerdata: *mut c_void) {
+    let out = &mut *(handlerdata as *mut Vec<u8>);
+    out.extend_from_slice(from_raw_parts(record as *const u8, reclen as usize));
+}
+
+impl MSPackParam {
+    pub fn new(sid: &str, start: time::OffsetDateTime, samprate: f64) -> Self {
+        Self {
+            sid: sid.to_string(),
+            starttime: time_to_nstime(start),
+            samprate,
+            encoding: None,
+            reclen: 4096,
+            pubversion: 1,
+            extra: None,
+            flags: libmseed_sys::MSF_FLUSHDATA,
+            byteorder: None,
+            recflags: 0,
+            seqnum: None,
+            verbose: 0,
+        }
+    }
+    /// Encoding of the packed data, defaults to Steim2 for i32
+    /// and Float32/Float64 for floating point data
+    pub fn encoding(&mut self, encoding: MSEncoding) {
+        self.encoding = Some(encoding);
+    }
+    pub fn record_length(&mut self, reclen: i32) {
+        self.reclen = reclen;
+    }
+    pub fn pubversion(&mut self, pubversion: u8) {
+        self.pubversion = pubversion;
+    }
+    /// Set the publication version from a miniSEED2 data quality indicator
+    ///
+    /// One of R, D, Q or M, see [crate::quality_to_pubversion].
+    pub fn data_quality(&mut self, quality: char) -> Result<(), MSError> {
+        let v = quality_to_pubversion(quality)
+            .ok_or_else(|| MSError::Error(format!("Error: unknown data quality: {}", quality)))?;
+        self.pubversion = v;
+        Ok(())
+    }
+    pub fn format_version2(&mut self, v2: bool) {
+        if v2 {
+            self.flags |= libmseed_sys::MSF_PACKVER2;
+        } else {
+            self.flags &= !libmseed_sys::MSF_PACKVER2;
+        }
+    }
+    /// Record flags, bit 0: calibration signals present, bit 1: time tag is
+    /// questionable, bit 2: clock locked, see the miniSEED3 specification
+    pub fn record_flags(&mut self, flags: u8) {
+        self.recflags = flags;
+    }
+    /// Sequence number of the first miniSEED2 record, incremented for each
+    /// following record and wrapping after 999999
+    ///
+    /// miniSEED3 records do not have a sequence number.  Defaults to the
+    /// sequence numbers written by libmseed.
+    pub fn sequence_number(&mut self, seqnum: Option<u32>) -> Result<(), MSError> {
+        if let Some(n) = seqnum {
+            if n > 999_999 {
+                return Err(MSError::Error(format!(
+                    "Error: sequence number too large: {}",
+                    n
+                )));
+            }
+        }
+        self.seqnum = seqnum;
+        Ok(())
+    }
+    /// Required byte order of the packed records, defaults to the library default
+    ///
+    /// libmseed writes miniSEED2 big endian and miniSEED3 little endian,
+    /// packing returns an error if the byte order cannot be written in the
+    /// selected format version, see [MSPackParam::format_version2].
+    pub fn byte_order(&mut self, order: Option<ByteOrder>) {
+        self.byteorder = order;
+    }
+    /// Extra headers placed into each packed miniSEED3 record
+    ///
+    /// The string is not validated and should be a JSON object,
+    /// e.g. `{"FDSN":{"Time":{"Quality":100}}}`.  When packing miniSEED2
+    /// only the headers libmseed maps to blockettes are stored, e.g.
+    /// `FDSN.Time.Quality` as blockette 1001 and event detections,
+    /// calibrations and time exceptions as blockettes 200/201, 300-390 and
+    /// 500, all other extra headers are dropped.
+    pub fn extra_headers(&mut self, extra: Option<&str>) -> Result<(), MSError> {
+        self.extra = match extra {
+            None => None,
+            Some(x) => {
+                if x.len() > u16::MAX as usize {
+                    return Err(MSError::Error(format!(
+                        "Extra headers too long: {} bytes",
+                        x.len()
+                    )));
+                }
+                let x = CString::new(x)
+                    .map_err(|_| MSError::Error("Extra headers contain a null byte".into()))?;
+                Some(x)
+            }
+        };
+        Ok(())
+    }
+    pub fn verbose(&mut self, verbose: bool) {
+        self.verbose = if verbose { 1 } else { 0 };
+    }
+    pub fn pack_i32(&self, data: &[i32]) -> Result<Vec<u8>, MSError> {
+        let enc = self.encoding.unwrap_or(MSEncoding::Steim2);
+        self.pack(data.as_ptr() as *mut c_void, data.len(), 4, 'i', enc)
+    }
+    pub fn pack_f32(&self, data: &[f32]) -> Result<Vec<u8>, MSError> {
+        let enc = self.encoding.unwrap_or(MSEncoding::Float32);
+        self.pack(data.as_ptr() as *mut c_void, data.len(), 4, 'f', enc)
+    }
+    pub fn pack_f64(&self, data: &[f64]) -> Result<Vec<u8>, MSError> {
+        let enc = self.encoding.unwrap_or(MSEncoding::Float64);
+        self.pack(data.as_ptr() as *mut c_void, data.len(), 8, 'd', enc)
+    }
+    /// Pack text, one byte per sample, with encoding Text
+    pub fn pack_text(&self, text: &str) -> Result<Vec<u8>, MSError> {
+        let data = text.as_bytes();
+        self.pack(
+            data.as_ptr() as *mut c_void,
+            data.len(),
+            1,
+            'a',
+            MSEncoding::Text,
+        )
+    }
+    // Pack f64 samples into whole records only, unless `flush`, returning
+    // the records, the number of samples packed and the number of records
+    pub(crate) fn pack_f64_records(
+        &self,
+        data: &[f64],
+        flush: bool,
+    ) -> Result<(Vec<u8>, usize, usize), MSError> {
+        let enc = self.encoding.unwrap_or(MSEncoding::Float64);
+        let flags = if flush {
+            self.flags | libmseed_sys::MSF_FLUSHDATA
+        } else {
+            self.flags & !libmseed_sys::MSF_FLUSHDATA
+        };
+        self.pack_records(data.as_ptr() as *mut c_void, data.len(), 8, 'd', enc, flags)
+    }
+    fn pack(
+        &self,
+        data: *mut c_void,
+        n: usize,
+        size: usize,
+        sampletype: char,
+        encoding: MSEncoding,
+    ) -> Result<Vec<u8>, MSError> {
+        let (out, packed, _) =
+            self.pack_records(data, n, size, sampletype, encoding, self.flags)?;
+        if packed != n {
+            return Err(MSError::Error(format!(
+                "Error: packed {} of {} samples",
+                packed, n
+            )));
+        }
+        Ok(out)
+    }
+    fn pack_records(
+        &self,
+        data: *mut c_void,
+        n: usize,
+        size: usize,
+        sampletype: char,
+        encoding: MSEncoding,
+        flags: u32,
+    ) -> Result<(Vec<u8>, usize, usize), MSError> {
+        let v2 = self.flags & libmseed_sys::MSF_PACKVER2 != 0;
+        match self.byteorder {
+            Some(ByteOrder::Little) if v2 => {
+                return Err(MSError::Error(
+                    "Error: miniSEED2 is only packed big endian".into(),
+                ))
+            }
+            Some(ByteOrder::Big) if !v2 => {
+                return Err(MSError::Error(
+                    "Error: miniSEED3 is only packed little endian".into(),
+                ))
+            }
+            _ => {}
+        }
+        let code = encoding.as_i8()?;
+        let mut out: Vec<u8> = vec![];
+        let mut msr: *mut MS3Record = unsafe { libmseed_sys::msr3_init(ptr::null_mut()) };
+        if msr.is_null() {
+            return Err(MSError::Error("Error allocating record".into()));
+        }
+        let m = unsafe { &mut *msr };
+        if let Err(e) = string_to_i8(&mut m.sid, &self.sid) {
+            unsafe { libmseed_sys::msr3_free(&mut msr) };
+            return Err(e);
+        }
+        m.starttime = self.starttime;
+        m.samprate = self.samprate;
+        m.reclen = self.reclen;
+        m.pubversion = self.pubversion as _;
+        m.flags = self.recflags;
+        m.encoding = code as _;
+        m.sampletype = sampletype as _;
+        m.datasamples = data;
+        m.datasize = (n * size) as _;
+        m.numsamples = n as _;
+        m.samplecnt = n as _;
+        if let Some(extra) = &self.extra {
+            m.extra = extra.as_ptr() as *mut c_char;
+            m.extralength = extra.as_bytes().len() as _;
+        }
+        let mut packed: i64 = 0;
+        let rv = unsafe {
+            libmseed_sys::msr3_pack(
+                msr,
+                Some(record_handler),
+                (&mut out) as *mut Vec<u8> as *mut c_void,
+                &mut packed,
+                flags,
+                self.verbose,
+            )
+        };
+        // Memory owned by rust, do not let libmseed free it
+        m.datasamples = ptr::null_mut();
+        m.extra = ptr::null_mut();
+        m.extralength = 0;
+        unsafe { libmseed_sys::msr3_free(&mut msr) };
+        let records = check_count(rv)? as usize;
+        if let (Some(seqnum), true) = (self.seqnum, v2) {
+            // Fixed length records, sequence number is the first 6 bytes
+            for (i, rec) in out.chunks_mut(self.reclen as usize).enumerate() {
+                let n = (seqnum as usize + i) % 1_000_000;
+                rec[..6].copy_from_slice(format!("{:06}", n).as_bytes());
+            }
+        }
+        Ok((out, packed as usize, records))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MSFileParam;
+    #[test]
+    fn pack_extra_headers() {
+        let start = crate::tests::t0();
+        let extra = r#"{"Provenance":{"Step":"detrend"}}"#;
+        let mut pp = MSPackParam::new("FDSN:XX_TEST_00_B_H_Z", start, 20.0);
+        pp.extra_headers(Some(extra)).unwrap();
+        let data: Vec<i32> = (0..1000).collect();
+        let buf = pp.pack_i32(&data).unwrap();
+
+        let file = std::env::temp_dir().join("libmseed_pack_extra.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        let mut n = 0;
+        for r in MSFileParam::new(&file) {
+            let rec = r.unwrap();
+            assert_eq!(rec.extra_headers().as_deref(), Some(extra));
+            n += rec.numsamples();
+        }
+        assert_eq!(n, 1000);
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn pack_header_fields() {
+        let start = crate::tests::t0();
+        let data: Vec<i32> = (0..5000).collect();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST_00_B_H_Z", start, 20.0);
+        pp.record_length(512);
+        pp.record_flags(0b110);
+        let v3 = pp.pack_i32(&data).unwrap();
+        pp.format_version2(true);
+        pp.sequence_number(Some(999_998)).unwrap();
+        let v2 = pp.pack_i32(&data).unwrap();
+        assert!(v2.len() >= 3 * 512);
+        assert_eq!(&v2[..6], b"999998");
+        assert_eq!(&v2[512..518], b"999999");
+        assert_eq!(&v2[1024..1030], b"000000");
+        for buf in &[v2, v3] {
+            for r in crate::MSBufferParam::new(buf.clone()) {
+                assert_eq!(r.unwrap().header().flags, 0b110);
+            }
+        }
+        assert!(pp.sequence_number(Some(1_000_000)).is_err());
+    }
+    #[test]
+    fn pack_byte_order() {
+        let start = crate::tests::t0();
+        let data: Vec<i32> = (0..1000).collect();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST_00_B_H_Z", start, 20.0);
+        pp.byte_order(Some(ByteOrder::Little));
+        let v3 = pp.pack_i32(&data).unwrap();
+        // Year of the start time, miniSEED3 offset 8
+        assert_eq!(v3[8..10], 2020u16.to_le_bytes());
+        pp.byte_order(Some(ByteOrder::Big));
+        assert!(pp.pack_i32(&data).is_err());
+
+        pp.format_version2(true);
+        let v2 = pp.pack_i32(&data).unwrap();
+        // Year of the start time, miniSEED2 offset 20
+        assert_eq!(v2[20..22], 2020u16.to_be_bytes());
+        pp.byte_order(Some(ByteOrder::Little));
+        assert!(pp.pack_i32(&data).is_err());
+
+        for buf in &[v2, v3] {
+            let mut mstl = crate::MSTraceList::from_buffer(buf.clone());
+            mstl.read().unwrap();
+            assert_eq!(crate::tests::first_segment(&mstl).to_vec_i32(), data);
+        }
+    }
+}
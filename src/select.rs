@@ -0,0 +1,182 @@
+use libmseed_sys::MS3Selections;
+use std::ffi::CString;
+use std::fmt;
+use std::ptr;
+
+use crate::{i8_to_string, nstime_to_time, time_to_nstime, MSError, MSRecord, SourceId};
+
+/// Source id patterns and time windows selecting records while reading,
+/// see [crate::MSTraceList::selections]
+pub struct Selections {
+    sel: *mut MS3Selections,
+}
+
+impl Default for Selections {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Selections {
+    /// Empty selections, matching no records
+    pub fn new() -> Self {
+        Self {
+            sel: ptr::null_mut(),
+        }
+    }
+    /// Selections of the source id and time window of each request
+    pub fn from_requests(
+        requests: &[(SourceId, time::OffsetDateTime, time::OffsetDateTime)],
+    ) -> Result<Self, MSError> {
+        let mut sel = Self::new();
+        for (id, start, end) in requests {
+            sel.add(&id.to_sid()?, Some(*start), Some(*end), 0)?;
+        }
+        Ok(sel)
+    }
+    /// Add a source id pattern and time window
+    ///
+    /// `pattern` may contain the globbing characters `*`, `?` and `[]`,
+    /// e.g. `FDSN:IU_*_B_H_?`.  A time of None and a publication version of 0
+    /// match any record.
+    pub fn add(
+        &mut self,
+        pattern: &str,
+        start: Option<time::OffsetDateTime>,
+        end: Option<time::OffsetDateTime>,
+        pubversion: u8,
+    ) -> Result<(), MSError> {
+        let pattern = CString::new(pattern)
+            .map_err(|_| MSError::Error(format!("Error: invalid pattern: {}", pattern)))?;
+        // An unset time matches any time
+        let unset = libmseed_sys::NSTUNSET as i64;
+        let start = start.map(time_to_nstime).unwrap_or(unset);
+        let end = end.map(time_to_nstime).unwrap_or(unset);
+        let rv = unsafe {
+            libmseed_sys::ms3_addselect(
+                &mut self.sel,
+                pattern.as_ptr() as *mut _,
+                start,
+                end,
+                pubversion,
+            )
+        };
+        if rv < 0 {
+            return Err(MSError::Error(format!(
+                "Error: adding selection {:?} from {} to {}",
+                pattern,
+                nstime_to_time(start),
+                nstime_to_time(end)
+            )));
+        }
+        Ok(())
+    }
+    /// True if no selections have been added
+    pub fn is_empty(&self) -> bool {
+        self.sel.is_null()
+    }
+    /// True if any part of the record is selected
+    pub fn matches(&self, rec: &MSRecord) -> bool {
+        if self.is_empty() {
+            return false;
+        }
+        let m = unsafe { libmseed_sys::msr3_matchselect(self.sel, rec.0, ptr::null_mut()) };
+        !m.is_null()
+    }
+    pub(crate) fn as_ptr(&self) -> *mut MS3Selections {
+        self.sel
+    }
+}
+
+// Debug output shows the source id patterns
+impl fmt::Debug for Selections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut patterns = vec![];
+        let mut sel = self.sel;
+        while !sel.is_null() {
+            let s = unsafe { &*sel };
+            patterns.push(i8_to_string(&s.sidpattern));
+            sel = s.next;
+        }
+        f.debug_struct("Selections")
+            .field("patterns", &patterns)
+            .finish()
+    }
+}
+
+impl Drop for Selections {
+    fn drop(&mut self) {
+        if !self.sel.is_null() {
+            unsafe { libmseed_sys::ms3_freeselections(self.sel) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MSFileParam, MSPackParam, MSTraceList};
+    #[test]
+    fn selections_from_requests() {
+        let t0 = crate::tests::t0();
+        let data: Vec<i32> = (0..100).collect();
+        let mut buf = vec![];
+        for cha in &["Z", "N", "E"] {
+            for k in 0..3 {
+                let sid = format!("FDSN:XX_TEST__B_H_{}", cha);
+                let t = t0 + time::Duration::seconds(10 * k);
+                buf.extend(MSPackParam::new(&sid, t, 10.0).pack_i32(&data).unwrap());
+            }
+        }
+        let id = |cha: &str| SourceId::from_sid(&format!("FDSN:XX_TEST__B_H_{}", cha)).unwrap();
+        let sel = Selections::from_requests(&[
+            (id("Z"), t0, t0 + time::Duration::seconds(5)),
+            (
+                id("E"),
+                t0 + time::Duration::seconds(25),
+                t0 + time::Duration::seconds(60),
+            ),
+        ])
+        .unwrap();
+        let file = std::env::temp_dir().join("libmseed_selections.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        let selected: Vec<_> = MSFileParam::new(&file)
+            .map(|r| r.unwrap())
+            .filter(|r| sel.matches(r))
+            .map(|r| (r.channel(), r.start_time()))
+            .collect();
+        assert_eq!(
+            selected,
+            vec![
+                ("BHZ".to_string(), t0),
+                ("BHE".to_string(), t0 + time::Duration::seconds(20)),
+            ]
+        );
+
+        let read_selected = |mut mstl: MSTraceList| {
+            let window = (id("N"), t0, t0 + time::Duration::seconds(60));
+            mstl.selections(Some(Selections::from_requests(&[window]).unwrap()));
+            mstl.count_records(true);
+            mstl.read().unwrap();
+            assert_eq!(mstl.channels(), vec![id("N")]);
+            assert_eq!(mstl.records_read(), Some(9));
+            assert_eq!(mstl.records_skipped(), Some(6));
+        };
+        read_selected(MSTraceList::new(&file));
+        read_selected(MSTraceList::from_buffer(buf));
+        assert!(!Selections::new().matches(&MSFileParam::new(&file).next().unwrap().unwrap()));
+        // Empty selections are not passed to libmseed, which would read every record
+        let mut mstl = MSTraceList::new(&file);
+        mstl.selections(Some(Selections::new()));
+        assert!(mstl.read().is_err());
+        assert_eq!(
+            format!("{:?}", sel),
+            "Selections { patterns: [\"FDSN:XX_TEST__B_H_Z\", \"FDSN:XX_TEST__B_H_E\"] }"
+        );
+        assert_eq!(
+            format!("{:?}", Selections::new()),
+            "Selections { patterns: [] }"
+        );
+        std::fs::remove_file(&file).unwrap();
+    }
+}
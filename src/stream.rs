@@ -0,0 +1,326 @@
+use libmseed_sys::MS3Record;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::slice::from_raw_parts;
+
+use crate::{
+    i8_to_string, io_error, nstime_to_time, rate_tolerable, samprate_hz, MSError, MSFileParam,
+    MSRecord, MSSampleType,
+};
+
+/// Samples of consecutive, time continuous records of a single source id
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamSegment {
+    pub sid: String,
+    /// Time of the first sample
+    pub start_nstime: i64,
+    /// Time of the last sample
+    pub end_nstime: i64,
+    /// Sample rate in Hz
+    pub samprate: f64,
+    pub samples: Vec<f64>,
+}
+
+impl StreamSegment {
+    pub fn start_time(&self) -> time::OffsetDateTime {
+        nstime_to_time(self.start_nstime)
+    }
+    pub fn end_time(&self) -> time::OffsetDateTime {
+        nstime_to_time(self.end_nstime)
+    }
+    // Record continues this segment, same source id and rate within
+    // libmseed's tolerances, the first sample within half a sample period
+    // of the expected time
+    fn continues(&self, sid: &str, start: i64, samprate: f64) -> bool {
+        if self.sid != sid
+            || self.samprate <= 0.0
+            || samprate <= 0.0
+            || !rate_tolerable(self.samprate, samprate)
+        {
+            return false;
+        }
+        let expected = unsafe { libmseed_sys::ms_sampletime(self.end_nstime, 1, self.samprate) };
+        let gap = (start - expected) as f64 / libmseed_sys::NSTMODULUS as f64;
+        gap.abs() <= 0.5 / self.samprate
+    }
+}
+
+/// Streams [StreamSegment]s from a file, see [MSFileParam::into_segments]
+#[derive(Debug)]
+pub struct StreamSegments {
+    fp: MSFileParam,
+    current: Option<StreamSegment>,
+}
+
+impl MSFileParam {
+    /// Group consecutive records into segments while reading
+    ///
+    /// A segment is returned once a record of a different source id or a
+    /// gap or overlap is found. Only one segment is held in memory at a time.
+    /// Records of text data are skipped.
+    pub fn into_segments(mut self) -> StreamSegments {
+        self.unpack_data(true);
+        StreamSegments {
+            fp: self,
+            current: None,
+        }
+    }
+    /// Read records, skipping records that cannot be read
+    ///
+    /// After an error the file is searched byte by byte from just after the
+    /// start of the failed record for the next position libmseed detects as a
+    /// miniSEED record, and reading continues from there.  The whole of the
+    /// failed record is lost along with any records damaged so they are no
+    /// longer detected.  With a fixed record length, see
+    /// [MSFileParam::record_length], reading simply continues with the next
+    /// record.  Reading stops at an error that reads no data, e.g. a file that
+    /// cannot be opened.
+    pub fn skip_errors(self) -> SkipErrors {
+        SkipErrors {
+            fp: self,
+            next: 0,
+            errors: 0,
+        }
+    }
+}
+
+/// Reads records from a file skipping unreadable records, see [MSFileParam::skip_errors]
+#[derive(Debug)]
+pub struct SkipErrors {
+    fp: MSFileParam,
+    // Offset following the last record read
+    next: i64,
+    errors: u64,
+}
+
+impl SkipErrors {
+    /// Number of read errors skipped
+    pub fn errors(&self) -> u64 {
+        self.errors
+    }
+}
+
+// Offset of the next detectable record at or after `from`
+fn find_record(path: &str, from: i64) -> Result<Option<i64>, MSError> {
+    const CHUNK: usize = 1 << 20;
+    // Bytes kept from the end of a chunk for detecting a record header
+    const HEADER: usize = 4096;
+    let mut file = File::open(path).map_err(io_error)?;
+    let mut pos = from as u64;
+    let mut buf = vec![0u8; CHUNK];
+    loop {
+        file.seek(SeekFrom::Start(pos)).map_err(io_error)?;
+        let mut n = 0;
+        while n < CHUNK {
+            match file.read(&mut buf[n..]).map_err(io_error)? {
+                0 => break,
+                k => n += k,
+            }
+        }
+        let last = n < CHUNK;
+        let end = if last { n } else { n - HEADER };
+        for i in 0..end {
+            let mut version: u8 = 0;
+            let rv = unsafe {
+                libmseed_sys::ms3_detect(buf[i..n].as_ptr() as *const _, (n - i) as _, &mut version)
+            };
+            if rv >= 0 {
+                return Ok(Some(pos as i64 + i as i64));
+            }
+        }
+        if last {
+            return Ok(None);
+        }
+        pos += end as u64;
+    }
+}
+
+// Position of the open file read with a fixed record length
+fn fixed_position(fp: &mut MSFileParam) -> Option<u64> {
+    fp.file.as_mut()?.stream_position().ok()
+}
+
+impl Iterator for SkipErrors {
+    type Item = MSRecord;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = fixed_position(&mut self.fp).unwrap_or(0);
+            match self.fp.read_record() {
+                Ok(rec) => {
+                    self.next = self.fp.offset() + rec.reclen() as i64;
+                    return Some(rec);
+                }
+                Err(MSError::EOF) => return None,
+                Err(_) => self.errors += 1,
+            }
+            if self.fp.reclen.is_some() {
+                // Errors that read no bytes, e.g. the file cannot be opened,
+                // would repeat forever
+                match fixed_position(&mut self.fp) {
+                    Some(pos) if pos > start => continue,
+                    _ => return None,
+                }
+            }
+            match find_record(&self.fp.path, self.next + 1) {
+                Ok(Some(offset)) => {
+                    // A negative position is a starting offset for libmseed
+                    self.next = offset;
+                    self.fp.fpos = -offset;
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+// Decoded numeric samples of a record, None for text or undecoded records
+pub(crate) fn record_samples(m: &MS3Record) -> Option<Vec<f64>> {
+    let n = m.numsamples.max(0) as usize;
+    let t = MSSampleType::try_from(m.sampletype).ok()?;
+    if t == MSSampleType::Text
+        || m.datasamples.is_null()
+        || (n * t.sample_size()) as u64 > m.datasize
+    {
+        return None;
+    }
+    let data = m.datasamples;
+    let v = unsafe {
+        match t {
+            MSSampleType::Integer32 => from_raw_parts(data as *const i32, n)
+                .iter()
+                .map(|x| *x as f64)
+                .collect(),
+            MSSampleType::Float32 => from_raw_parts(data as *const f32, n)
+                .iter()
+                .map(|x| *x as f64)
+                .collect(),
+            _ => from_raw_parts(data as *const f64, n).to_vec(),
+        }
+    };
+    Some(v)
+}
+
+impl Iterator for StreamSegments {
+    type Item = Result<StreamSegment, MSError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut msr = match self.fp.read_msr(self.fp.flags) {
+                Ok(msr) => msr,
+                Err(MSError::EOF) => return self.current.take().map(Ok),
+                Err(e) => return Some(Err(e)),
+            };
+            let m = unsafe { *msr };
+            let samples = record_samples(&m);
+            unsafe { libmseed_sys::msr3_free(&mut msr) };
+            let mut samples = match samples {
+                Some(samples) if !samples.is_empty() => samples,
+                _ => continue,
+            };
+            let sid = i8_to_string(&m.sid);
+            let samprate = samprate_hz(m.samprate);
+            let end = unsafe {
+                libmseed_sys::ms_sampletime(m.starttime, samples.len() as i64 - 1, samprate)
+            };
+            if let Some(seg) = self.current.as_mut() {
+                if seg.continues(&sid, m.starttime, samprate) {
+                    seg.samples.append(&mut samples);
+                    seg.end_nstime = end;
+                    continue;
+                }
+            }
+            let next = StreamSegment {
+                sid,
+                start_nstime: m.starttime,
+                end_nstime: end,
+                samprate,
+                samples,
+            };
+            if let Some(done) = self.current.replace(next) {
+                return Some(Ok(done));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{MSPackParam, MSTraceList};
+    #[test]
+    fn stream_segments() {
+        let mut mstl = MSTraceList::new("./tests/multiple.seed");
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        let segs: Vec<_> = crate::MSFileParam::new("./tests/multiple.seed")
+            .into_segments()
+            .map(|s| s.unwrap())
+            .collect();
+        assert_eq!(segs.len(), trace.numsegments() as usize);
+        assert_eq!(segs[0].samples.len(), 288000);
+        assert_eq!(segs[0].start_time(), trace.start_time());
+        assert_eq!(segs[0].end_time(), trace.end_time());
+    }
+    #[test]
+    fn skip_corrupt_record() {
+        let mut buf = std::fs::read("./tests/multiple.seed").unwrap();
+        let n = buf.len() / 512;
+        // Overwrite the header of a record in the middle
+        for b in buf[10 * 512..10 * 512 + 64].iter_mut() {
+            *b = b'x';
+        }
+        let file = std::env::temp_dir().join("libmseed_skip_corrupt.mseed");
+        std::fs::write(&file, &buf).unwrap();
+
+        let mut it = crate::MSFileParam::new(&file).skip_errors();
+        let starts: Vec<_> = (&mut it).map(|r| r.header().start_nstime).collect();
+        assert_eq!(starts.len(), n - 1);
+        assert_eq!(it.errors(), 1);
+        let all: Vec<_> = crate::MSFileParam::new("./tests/multiple.seed")
+            .map(|r| r.unwrap().header().start_nstime)
+            .collect();
+        assert_eq!(starts[..10], all[..10]);
+        assert_eq!(starts[10..], all[11..]);
+        std::fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn skip_errors_unreadable() {
+        let dir = std::env::temp_dir();
+        let missing = dir.join("libmseed_skip_missing.mseed");
+        for path in &[missing, dir] {
+            for reclen in &[None, Some(512)] {
+                let mut fp = crate::MSFileParam::new(path);
+                fp.record_length(*reclen);
+                // Reading stops instead of retrying the same error
+                assert!(fp.skip_errors().next().is_none());
+            }
+        }
+    }
+    #[test]
+    fn stream_segments_gap() {
+        let t0 = crate::tests::t0();
+        let data: Vec<i32> = (0..100).collect();
+        let sid = "FDSN:XX_TEST__B_H_Z";
+        let mut buf = vec![];
+        // Continuous, jittered by less than half a sample, then a gap
+        for ms in &[0, 10_020, 20_000, 40_000] {
+            let t = t0 + time::Duration::milliseconds(*ms);
+            buf.extend(MSPackParam::new(sid, t, 10.0).pack_i32(&data).unwrap());
+        }
+        buf.extend(
+            MSPackParam::new("FDSN:XX_TEST__B_H_N", t0, 10.0)
+                .pack_i32(&data)
+                .unwrap(),
+        );
+        let file = std::env::temp_dir().join("libmseed_stream_gap.mseed");
+        std::fs::write(&file, &buf).unwrap();
+        let segs: Vec<_> = crate::MSFileParam::new(&file)
+            .into_segments()
+            .map(|s| s.unwrap())
+            .collect();
+        let lens: Vec<_> = segs.iter().map(|s| s.samples.len()).collect();
+        assert_eq!(lens, vec![300, 100, 100]);
+        assert_eq!(segs[1].start_time(), t0 + time::Duration::seconds(40));
+        assert_eq!(segs[2].sid, "FDSN:XX_TEST__B_H_N");
+        std::fs::remove_file(&file).unwrap();
+    }
+}
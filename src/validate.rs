@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::{nstime_to_time, MSError, MSFileParam, MSRecord};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationIssue {
+    /// Record could not be read, including CRC failures; reading stops here
+    ReadError(String),
+    UnknownEncoding(i16),
+    BadSampleRate(f64),
+    /// Record starts before the previous record of the same source id
+    TimeOrder {
+        previous: time::OffsetDateTime,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordIssue {
+    /// File offset of the record
+    pub offset: i64,
+    pub sid: String,
+    pub issue: ValidationIssue,
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    /// Number of records read
+    pub records: u64,
+    pub issues: Vec<RecordIssue>,
+}
+
+impl ValidationReport {
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Tracks the start time of the last record of each source id to detect
+/// records out of chronological order while streaming
+#[derive(Debug, Default)]
+pub struct TimeOrderCheck {
+    last: HashMap<String, i64>,
+}
+
+impl TimeOrderCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Check a record against the previous record of the same source id
+    ///
+    /// Returns the start time of the previous record if this record starts
+    /// before it.
+    pub fn check(&mut self, rec: &MSRecord) -> Option<time::OffsetDateTime> {
+        let start = rec.ptr().starttime;
+        let prev = self.last.insert(rec.sid(), start)?;
+        if start < prev {
+            Some(nstime_to_time(prev))
+        } else {
+            None
+        }
+    }
+}
+
+/// File offsets of records starting before the previous record of the same source id
+pub fn out_of_order_records<S: AsRef<Path>>(file: S) -> Result<Vec<i64>, MSError> {
+    let mut fp = MSFileParam::new(file);
+    fp.unpack_data(false);
+    let mut order = TimeOrderCheck::new();
+    let mut out = vec![];
+    loop {
+        let rec = match fp.read_record() {
+            Ok(rec) => rec,
+            Err(MSError::EOF) => break,
+            Err(e) => return Err(e),
+        };
+        if order.check(&rec).is_some() {
+            out.push(fp.offset());
+        }
+    }
+    Ok(out)
+}
+
+fn known_encoding(encoding: i16) -> bool {
+    let mut samplesize: u8 = 0;
+    let mut sampletype: std::os::raw::c_char = 0;
+    let rv = unsafe {
+        libmseed_sys::ms_encoding_sizetype(encoding as _, &mut samplesize, &mut sampletype)
+    };
+    rv == 0
+}
+
+/// Read a file only to validate it, no data samples are unpacked
+///
+/// Checks each record for CRC errors (miniSEED3), unknown encodings,
+/// invalid sample rates and records starting before the previous record
+/// of the same source id.
+pub fn validate_file<S: AsRef<Path>>(file: S) -> ValidationReport {
+    let mut report = ValidationReport::default();
+    let mut fp = MSFileParam::new(file);
+    fp.unpack_data(false);
+    fp.validate_crc(true);
+    let mut order = TimeOrderCheck::new();
+    loop {
+        let rec = match fp.read_record() {
+            Ok(rec) => rec,
+            Err(MSError::EOF) => break,
+            Err(MSError::Error(e)) => {
+                report.issues.push(RecordIssue {
+                    offset: fp.offset(),
+                    sid: String::new(),
+                    issue: ValidationIssue::ReadError(e),
+                });
+                break;
+            }
+        };
+        report.records += 1;
+        let sid = rec.sid();
+        let offset = fp.offset();
+        let mut issue = |x| {
+            report.issues.push(RecordIssue {
+                offset,
+                sid: sid.clone(),
+                issue: x,
+            })
+        };
+        let m = rec.ptr();
+        if !known_encoding(m.encoding) && m.samplecnt > 0 {
+            issue(ValidationIssue::UnknownEncoding(m.encoding));
+        }
+        if !m.samprate.is_finite() || (m.samprate == 0.0 && m.samplecnt > 0) {
+            issue(ValidationIssue::BadSampleRate(m.samprate));
+        }
+        if let Some(previous) = order.check(&rec) {
+            issue(ValidationIssue::TimeOrder { previous });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MSPackParam;
+    #[test]
+    fn validate_good_file() {
+        let report = validate_file("./tests/multiple.seed");
+        assert!(report.is_valid());
+        assert_eq!(
+            report.records as usize,
+            MSFileParam::new("./tests/multiple.seed").count()
+        );
+    }
+    #[test]
+    fn validate_time_order() {
+        let t0 = crate::tests::t0();
+        let t1 = t0 + time::Duration::seconds(100);
+        let data: Vec<i32> = (0..100).collect();
+        let sid = "FDSN:XX_TEST__B_H_Z";
+        let mut buf = MSPackParam::new(sid, t1, 1.0).pack_i32(&data).unwrap();
+        let n = buf.len() as i64;
+        buf.extend(MSPackParam::new(sid, t0, 1.0).pack_i32(&data).unwrap());
+        let file = std::env::temp_dir().join("libmseed_validate_order.mseed");
+        std::fs::write(&file, &buf).unwrap();
+
+        let report = validate_file(&file);
+        assert_eq!(report.records, 2);
+        assert_eq!(
+            report.issues,
+            vec![RecordIssue {
+                offset: n,
+                sid: sid.to_string(),
+                issue: ValidationIssue::TimeOrder { previous: t1 },
+            }]
+        );
+        assert_eq!(out_of_order_records(&file).unwrap(), vec![n]);
+        assert!(out_of_order_records("./tests/multiple.seed")
+            .unwrap()
+            .is_empty());
+        std::fs::remove_file(&file).unwrap();
+    }
+}
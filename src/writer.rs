@@ -0,0 +1,195 @@
+use std::io::Write;
+
+use crate::{io_error, samprate_hz, MSError, MSPackParam};
+
+/// When an [MSWriter] writes a partial record
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlushPolicy {
+    /// Only full records are written until [MSWriter::flush]
+    Manual,
+    /// Write all pending samples once this many samples are pending
+    Samples(usize),
+    /// Write all pending samples once they span this duration
+    Duration(time::Duration),
+}
+
+/// Packs samples into records as they arrive and writes them to `W`
+///
+/// Complete records are written as soon as enough samples are pushed,
+/// remaining samples are held until a full record is available or the
+/// [FlushPolicy] is met.  Pending samples are written as a partial record
+/// by [MSWriter::flush] or when the writer is dropped, where errors are
+/// ignored.
+///
+/// miniSEED has no flag marking records as continuous, readers merge records
+/// whose start time follows the last sample of the previous record. The start
+/// time of each record is computed from the start time of the first record
+/// and the number of samples written before it, so records join into a single
+/// segment without accumulated rounding.  miniSEED2 sequence numbers, if set
+/// with [MSPackParam::sequence_number], continue across records.
+///
+/// Samples are packed as f64, as with [MSPackParam::pack_f64].
+#[derive(Debug)]
+pub struct MSWriter<W: Write> {
+    w: W,
+    pp: MSPackParam,
+    policy: FlushPolicy,
+    // Start time of the first sample written
+    start: i64,
+    written: i64,
+    records: u64,
+    pending: Vec<f64>,
+}
+
+impl<W: Write> MSWriter<W> {
+    /// Writer of records with the source id, start time, sample rate, record
+    /// length and other header fields of `pp`
+    pub fn new(w: W, pp: MSPackParam) -> Self {
+        Self {
+            w,
+            start: pp.starttime,
+            pp,
+            policy: FlushPolicy::Manual,
+            written: 0,
+            records: 0,
+            pending: vec![],
+        }
+    }
+    pub fn flush_policy(&mut self, policy: FlushPolicy) {
+        self.policy = policy;
+    }
+    /// Length of following records, see [MSPackParam::record_length]
+    pub fn record_length(&mut self, reclen: i32) {
+        self.pp.record_length(reclen);
+    }
+    /// Number of records written
+    pub fn records_written(&self) -> u64 {
+        self.records
+    }
+    /// Number of samples waiting for a full record or flush
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+    // Pending samples that trigger writing a partial record
+    fn limit(&self) -> Option<usize> {
+        match self.policy {
+            FlushPolicy::Manual => None,
+            FlushPolicy::Samples(n) => Some(n.max(1)),
+            FlushPolicy::Duration(d) => {
+                let rate = samprate_hz(self.pp.samprate);
+                if rate <= 0.0 {
+                    return None;
+                }
+                let n = (d.as_seconds_f64() * rate).ceil();
+                Some(if n < 1.0 { 1 } else { n as usize })
+            }
+        }
+    }
+    /// Add samples, writing any complete records
+    pub fn push_samples(&mut self, data: &[f64]) -> Result<(), MSError> {
+        let mut data = data;
+        while !data.is_empty() {
+            let limit = self.limit();
+            let take = match limit {
+                Some(n) => n.saturating_sub(self.pending.len()).max(1).min(data.len()),
+                None => data.len(),
+            };
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            let flush = matches!(limit, Some(n) if self.pending.len() >= n);
+            self.write(flush)?;
+        }
+        Ok(())
+    }
+    /// Write all pending samples, the last record may be partially filled
+    pub fn flush(&mut self) -> Result<(), MSError> {
+        self.write(true)?;
+        self.w.flush().map_err(io_error)
+    }
+    fn write(&mut self, flush: bool) -> Result<(), MSError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let rate = samprate_hz(self.pp.samprate);
+        self.pp.starttime = unsafe { libmseed_sys::ms_sampletime(self.start, self.written, rate) };
+        let (buf, packed, records) = self.pp.pack_f64_records(&self.pending, flush)?;
+        self.w.write_all(&buf).map_err(io_error)?;
+        self.pending.drain(..packed);
+        self.written += packed as i64;
+        self.records += records as u64;
+        if let Some(n) = self.pp.seqnum {
+            self.pp.seqnum = Some((n + records as u32) % 1_000_000);
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for MSWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MSBufferParam, MSTraceList};
+    fn params() -> MSPackParam {
+        let t0 = crate::tests::t0();
+        let mut pp = MSPackParam::new("FDSN:XX_TEST__B_H_Z", t0, 40.0);
+        pp.record_length(512);
+        pp
+    }
+    fn samples_in(buf: &[u8]) -> i64 {
+        MSBufferParam::new(buf.to_vec())
+            .map(|r| r.unwrap().numsamples())
+            .sum()
+    }
+    #[test]
+    fn writer_full_records() {
+        let data: Vec<f64> = (0..1000).map(|x| x as f64 * 0.5).collect();
+        let mut buf = vec![];
+        {
+            let mut w = MSWriter::new(&mut buf, params());
+            for chunk in data.chunks(7) {
+                w.push_samples(chunk).unwrap();
+            }
+            // Only complete records written so far
+            assert_eq!(w.get_ref().len() as u64, w.records_written() * 512);
+            assert!(w.records_written() > 0);
+            assert_eq!(samples_in(w.get_ref()) + w.pending() as i64, 1000);
+        }
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        let trace = mstl.traces().next().unwrap();
+        assert_eq!(trace.numsegments(), 1);
+        assert_eq!(trace.segments().next().unwrap().to_vec_f64(), data);
+    }
+    #[test]
+    fn writer_flush_policy() {
+        let data: Vec<f64> = (0..250).map(|x| x as f64).collect();
+        let mut buf = vec![];
+        let mut w = MSWriter::new(&mut buf, params());
+        w.flush_policy(FlushPolicy::Samples(100));
+        w.push_samples(&data).unwrap();
+        assert_eq!(w.pending(), 50);
+        assert_eq!(samples_in(w.get_ref()), 200);
+        w.flush().unwrap();
+        assert_eq!(w.pending(), 0);
+        assert_eq!(samples_in(w.get_ref()), 250);
+        // 40 samples per second
+        w.flush_policy(FlushPolicy::Duration(time::Duration::seconds(1)));
+        w.push_samples(&data[..100]).unwrap();
+        assert_eq!(w.pending(), 20);
+        assert_eq!(samples_in(w.get_ref()), 330);
+        drop(w);
+        assert_eq!(samples_in(&buf), 350);
+
+        let mut mstl = MSTraceList::from_buffer(buf);
+        mstl.read().unwrap();
+        assert_eq!(mstl.traces().next().unwrap().numsegments(), 1);
+    }
+}